@@ -1,33 +1,65 @@
-use std::net::IpAddr;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+/// Errors are cheap to clone, so that the same one can be recorded for
+/// every port it affects.
+#[derive(Debug, Clone, Error)]
 pub enum ScanError {
+    #[error("No target to scan")]
+    MissingTargets,
+    #[error("Timing template `{0}` is invalid, it goes from 0 to 5")]
+    InvalidTiming(u8),
     #[error("Port `{0}` is invalid")]
     InvalidPort(String),
-    #[error("Failed to check target kind (ensure it's a domain or IPv4). Origin: {0}")]
+    #[error("TCP flags `{0}` are invalid")]
+    InvalidFlags(String),
+    #[error("Target `{0}` is invalid")]
+    InvalidTarget(String),
+    #[error("Target `{0}` maps more than 65536 hosts")]
+    TooManyHosts(String),
+    #[error("Failed to check target kind (ensure it's a domain or an IP address). Origin: {0}")]
     HostParseFailed(#[source] url::ParseError),
     #[error("Failed to resolve the given target. Origin: {0}")]
-    ResolverFailed(#[source] std::io::Error),
-    #[error("Resolver didn't find any IPv4 address mapped by. Origin: `{0}`")]
+    ResolverFailed(#[source] Arc<io::Error>),
+    #[error(
+        "Resolver didn't find any address of the requested IP version mapped by. Origin: `{0}`"
+    )]
     DomainLookupFailed(String),
     #[error("You must run the scanner as sudo")]
     NormalUserRequired,
     #[error("No network interfaces available")]
     MissingDefaultInterface,
+    #[error("Network interface `{0}` wasn't found")]
+    InterfaceNotFound(String),
+    #[error("Probes are already sent through network interface `{0}` with other addresses")]
+    InterfaceInUse(String),
     #[error("Failed to create socket. Origin: {0}")]
-    DatalinkChannelFailed(#[source] std::io::Error),
+    DatalinkChannelFailed(#[source] Arc<io::Error>),
     #[error("Make sure the default network interface has an IPv4")]
-    OnlyIpv4InterfaceSupported,
-    #[error("Only supports IPv4 addresses or domains that map addresses with this IP version")]
-    OnlyIpv4TargetSupported,
+    MissingInterfaceIpv4,
+    #[error("Make sure the default network interface has an IPv6")]
+    MissingInterfaceIpv6,
+    #[error("Default network interface has no address of the IP version of target `{0}`")]
+    MissingSourceAddr(IpAddr),
+    #[error("Minimum rate `{0}` is higher than the maximum rate `{1}`")]
+    InvalidRateRange(u32, u32),
     #[error("Failed to get MAC address of gateway. Origin: {0}")]
     GatewayLookupFailed(String),
     #[error("Failed to get MAC address")]
     MissingMacAddr,
     #[error("Failed to send packet to `{0}`. Origin: {1}")]
-    PacketSendFailed(IpAddr, #[source] std::io::Error),
+    PacketSendFailed(IpAddr, #[source] Arc<io::Error>),
     #[error("Failed to receive packet to `{0}`. Origin: {1}")]
-    PacketRecvFailed(IpAddr, #[source] std::io::Error),
+    PacketRecvFailed(IpAddr, #[source] Arc<io::Error>),
+    #[error("Failed to probe `{0}`. Origin: {1}")]
+    ProbeFailed(SocketAddr, #[source] Arc<io::Error>),
+    #[error("Failed to receive packets from the network interface. Origin: {0}")]
+    ChannelRecvFailed(#[source] Arc<io::Error>),
+    #[error("Failed to write results to `{0}`. Origin: {1}")]
+    OutputFailed(String, #[source] Arc<io::Error>),
 }
@@ -1,82 +1,307 @@
+use std::{fs::File, io, ops::RangeInclusive, time::Duration};
+
 use anyhow::Result;
 use clap::{
-    arg, crate_authors, crate_name, crate_version, ArgAction, ArgGroup, ArgMatches, Command,
+    arg, builder::RangedU64ValueParser, crate_authors, crate_name, crate_version, ArgAction,
+    ArgGroup, ArgMatches, Command,
 };
 use pad::PadStr;
 use pscan::{
     error::ScanError,
-    is_user_sudo, logger, resolver,
-    scan::{PortsToScan, ScanResult, ScanType, Scanner, Technique},
+    logger,
+    output::Format,
+    scan::{
+        BannerGrab, FlagsStyle, PortState, PortsToScan, ScanFlags, ScanResult, Scanner,
+        ScannerBuilder, Technique,
+    },
 };
 
+// Packets per second sent by the stateless SYN scan if not told otherwise.
+const DEFAULT_RATE: u32 = 10000;
+
+// Flags in nmap's style and the arguments they stand for.
+const NMAP_FLAGS: &[(&str, &str)] = &[
+    ("-oJ", "--output-json"),
+    ("-oX", "--output-xml"),
+    ("-oG", "--output-grepable"),
+    ("-oA", "--output-all"),
+    ("-sV", "--service-version"),
+    ("-sF", "--fin"),
+    ("-sN", "--null"),
+    ("-sX", "--xmas"),
+    ("-sA", "--ack"),
+    ("-sW", "--window"),
+    ("-sM", "--maimon"),
+];
+
 struct ParsedArgs {
     debug: bool,
-    ports: PortsToScan,
-    techniques: Vec<Technique>,
-    target: String,
+    show_closed: bool,
+    scanner: ScannerBuilder,
+    /// Format printed instead of the table.
+    output: Option<Format>,
+    files: Vec<(Format, String)>,
+}
+
+/// Rewrites nmap's flags (e.g. `-oJ` or `-sV`), which clap can't take as
+/// short flags, into their long arguments.
+fn expand_nmap_flags(args: impl Iterator<Item = String>) -> Vec<String> {
+    args.map(
+        |arg| match NMAP_FLAGS.iter().find(|(short, _)| *short == arg) {
+            Some((_, long)) => String::from(*long),
+            None => arg,
+        },
+    )
+    .collect()
+}
+
+/// Parses a single port (`80`) or a range (`1-1024`), where a missing bound
+/// defaults to the first or last port (`-100`, `60000-` or just `-`).
+fn parse_port_range(raw: &str) -> Result<RangeInclusive<u16>, ScanError> {
+    let invalid = || ScanError::InvalidPort(String::from(raw));
+    // Port 0 is reserved and can't be scanned.
+    let parse_port = |rp: &str| match rp.parse::<u16>() {
+        Ok(0) | Err(_) => Err(invalid()),
+        Ok(port) => Ok(port),
+    };
+    let parse = |rp: &str, default: u16| match rp {
+        "" => Ok(default),
+        _ => parse_port(rp),
+    };
+
+    let range = match raw.split_once('-') {
+        Some((start, end)) => parse(start, 1)?..=parse(end, u16::MAX)?,
+        None => {
+            let port = parse_port(raw)?;
+            port..=port
+        }
+    };
+
+    if range.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(range)
+}
+
+/// Sorts the given ranges and merges the ones that overlap or touch, so
+/// that no port is scanned twice.
+fn merge_port_ranges(mut ranges: Vec<RangeInclusive<u16>>) -> Vec<RangeInclusive<u16>> {
+    ranges.sort_unstable_by_key(|r| *r.start());
+
+    let mut merged: Vec<RangeInclusive<u16>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                *last = *last.start()..=*last.end().max(range.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
 }
 
 fn parse_args(matches: ArgMatches) -> Result<ParsedArgs, ScanError> {
     let debug = matches.get_flag("debug");
+    let show_closed = matches.get_flag("show-closed");
+
+    let ports = match (
+        matches.get_many::<String>("port"),
+        matches.get_one::<usize>("top-ports"),
+    ) {
+        (Some(rps), _) => {
+            let ranges = merge_port_ranges(
+                rps.map(|rp| parse_port_range(rp))
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
 
-    let ports = match matches.get_many::<String>("port") {
-        Some(rps) => PortsToScan::Selected(
-            rps.map(|rp| match rp.parse::<u16>() {
-                Ok(p) => Ok(p),
-                Err(_) => Err(ScanError::InvalidPort(String::from(rp))),
-            })
+            if ranges.contains(&(1..=u16::MAX)) {
+                PortsToScan::Full
+            } else if ranges.iter().all(|r| r.start() == r.end()) {
+                PortsToScan::Selected(ranges.into_iter().map(|r| *r.start()).collect())
+            } else {
+                PortsToScan::Ranges(ranges)
+            }
+        }
+        (None, Some(&n)) => PortsToScan::Top(n),
+        (None, None) => PortsToScan::All,
+    };
+
+    let excluded = match matches.get_many::<String>("exclude-ports") {
+        Some(rps) => rps
+            .map(|rp| parse_port_range(rp))
             .collect::<Result<_, _>>()?,
-        ),
-        None => PortsToScan::All,
+        None => vec![],
     };
+    let ports = ports.excluding(excluded);
+
+    let rate = matches
+        .get_one::<u32>("rate")
+        .copied()
+        .unwrap_or(DEFAULT_RATE);
 
-    let techniques = matches
-        .get_many::<clap::Id>("techniques")
-        .unwrap()
-        .map(|rt| {
-            let technique = Technique::from(rt.as_str());
-            match technique.kind {
-                ScanType::Syn if !is_user_sudo() => Err(ScanError::NormalUserRequired),
-                _ => Ok(technique),
+    let mut scanner = Scanner::builder()
+        .targets(matches.get_many::<String>("target").unwrap())
+        .ipv6(matches.get_flag("ipv6"))
+        .ports(ports)
+        .timing(*matches.get_one::<u8>("timing").unwrap())
+        .congestion_control(matches.get_flag("congestion-control"))
+        .service_detection(matches.get_flag("service-version"))
+        .tls_inspection(matches.get_flag("ssl-info"));
+
+    for rt in matches.get_many::<clap::Id>("techniques").unwrap() {
+        scanner = scanner.technique(match rt.as_str() {
+            "stateless" => Technique::stateless_syn(rate),
+            "scanflags" => {
+                let flags = matches
+                    .get_one::<String>("scanflags")
+                    .unwrap()
+                    .parse::<ScanFlags>()?;
+                let style = match matches.get_one::<String>("scanflags-as").unwrap().as_str() {
+                    "fin" => FlagsStyle::Fin,
+                    _ => FlagsStyle::Syn,
+                };
+                Technique::scan_flags(flags, style)
             }
-        })
-        .collect::<Result<_, _>>()?;
+            raw => Technique::from(raw),
+        });
+    }
+
+    if let Some(&ms) = matches.get_one::<u64>("timeout") {
+        scanner = scanner.timeout(Duration::from_millis(ms));
+    }
+    if let Some(&retries) = matches.get_one::<usize>("retries") {
+        scanner = scanner.retries(retries);
+    }
+    if let Some(&threads) = matches.get_one::<usize>("max-parallelism") {
+        scanner = scanner.max_parallelism(threads);
+    }
+    if let Some(&pps) = matches.get_one::<u32>("max-rate") {
+        scanner = scanner.max_rate(pps);
+    }
+    if let Some(&pps) = matches.get_one::<u32>("min-rate") {
+        scanner = scanner.min_rate(pps);
+    }
+
+    if matches.get_flag("banner") {
+        let mut grab = BannerGrab::default();
+        if let Some(&ms) = matches.get_one::<u64>("banner-wait") {
+            grab.wait = Duration::from_millis(ms);
+        }
+        if let Some(&bytes) = matches.get_one::<usize>("banner-size") {
+            grab.max_len = bytes;
+        }
+        grab.probe = matches
+            .get_one::<String>("banner-probe")
+            .map(|probe| probe.clone().into_bytes());
+        scanner = scanner.banner(grab);
+    }
 
-    let target = matches.get_one::<String>("target").unwrap().to_owned();
+    // Text is the table, printed by hand.
+    let output = matches
+        .get_one::<String>("output")
+        .and_then(|name| Format::ALL.into_iter().find(|f| f.name() == name));
+
+    let mut files = [
+        ("output-json", Format::Json),
+        ("output-xml", Format::Xml),
+        ("output-grepable", Format::Grepable),
+        ("output-csv", Format::Csv),
+    ]
+    .into_iter()
+    .filter_map(|(id, format)| Some((format, matches.get_one::<String>(id)?.clone())))
+    .collect::<Vec<_>>();
+    if let Some(basename) = matches.get_one::<String>("output-all") {
+        files.extend(
+            Format::ALL.map(|format| (format, format!("{}.{}", basename, format.extension()))),
+        );
+    }
 
     Ok(ParsedArgs {
         debug,
-        ports,
-        techniques,
-        target,
+        show_closed,
+        scanner,
+        output,
+        files,
     })
 }
 
-fn print_results(result: ScanResult) {
-    let mut out = format!("Scan Duration: {:.4}s\n\n", result.elapsed.as_secs_f32());
-    if result.ports.is_empty() {
-        out.push_str("Didn't find any open port.\n");
-    } else {
-        out.push_str("Port    State      Scan Method\n");
+fn print_results(result: &ScanResult, show_closed: bool) {
+    let mut out = format!("Scan Duration: {:.4}s\n", result.elapsed.as_secs_f32());
+
+    result.hosts.iter().for_each(|hr| {
+        out.push_str(&format!("\nHost: {}\n", hr.ip));
+
+        let closed = hr.closed();
+        if closed > 0 && !show_closed {
+            out.push_str(&format!("Not shown: {} closed ports\n", closed));
+        }
+
+        let shown = hr
+            .ports
+            .iter()
+            .filter(|pr| show_closed || pr.state != PortState::Closed)
+            .collect::<Vec<_>>();
+        if shown.is_empty() {
+            out.push_str("Didn't find any open port.\n");
+            return;
+        }
+
+        // Services only have a column if any was identified.
+        let services = shown.iter().any(|pr| pr.service.is_some());
+        if services {
+            out.push_str(
+                "Port    State          Scan Method          Reason                  Service\n",
+            );
+        } else {
+            out.push_str("Port    State          Scan Method          Reason\n");
+        }
+
+        shown.iter().for_each(|pr| {
+            let reason = format!("{}", pr.reason);
+            let line = match &pr.service {
+                _ if !services => reason,
+                Some(service) => format!(
+                    "{}{} {}",
+                    reason.pad_to_width(24),
+                    service.name,
+                    service.description()
+                ),
+                None => reason.pad_to_width(24),
+            };
 
-        result.ports.iter().for_each(|pr| {
             out.push_str(&format!(
-                "{:<8}{}{}\n",
+                "{:<8}{}{}{}\n",
                 pr.port,
-                format!("{}", pr.state).pad_to_width(11),
-                pr.kind,
-            ))
+                format!("{}", pr.state).pad_to_width(15),
+                format!("{}", pr.kind).pad_to_width(21),
+                line.trim_end(),
+            ));
+
+            // Details go below the port, as nmap shows script output.
+            let details = pr
+                .banner
+                .iter()
+                .map(|banner| format!("banner: {}", banner))
+                .chain(pr.tls.iter().map(|tls| format!("tls: {}", tls.summary())))
+                .collect::<Vec<_>>();
+            for (n, detail) in details.iter().enumerate() {
+                let prefix = if n + 1 == details.len() { "|_" } else { "| " };
+                out.push_str(&format!("{:<8}{} {}\n", "", prefix, detail));
+            }
         });
-    }
+    });
 
     print!("{}", out);
 }
 
 fn main() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
     let arg_matches = Command::new(crate_name!())
         .about(
-            "Port scanner capable of inspecting the TCP protocol.\n\
-            SYN scan requires sudo user.",
+            "Port scanner capable of inspecting the TCP and UDP protocols.\n\
+            Every scan but the TCP one requires sudo user.",
         )
         .version(crate_version!())
         .arg_required_else_help(true)
@@ -84,21 +309,127 @@ fn main() -> Result<()> {
         .args([
             // Miscellaneous arguments.
             arg!(-d --debug "Turns on debugging information").action(ArgAction::SetTrue),
-            arg!(-p --port <PORT> "One or more ports separated by a comma").value_delimiter(','),
-            arg!([target] "Address or hostname to scan").required(true),
+            arg!(-'6' --ipv6 "Resolves hostnames to IPv6 addresses").action(ArgAction::SetTrue),
+            arg!(--"show-closed" "Lists closed ports instead of only counting them")
+                .action(ArgAction::SetTrue),
+            arg!(--"service-version" "Identifies the services listening on open TCP ports \
+                (same as -sV)")
+            .action(ArgAction::SetTrue),
+            arg!(--"ssl-info" "Describes the TLS protocol, cipher and certificate served on \
+                open TCP ports")
+            .action(ArgAction::SetTrue),
+            arg!(--output <FORMAT> "Format of the results printed on the standard output")
+                .value_parser(["text", "json", "xml", "grepable", "csv"])
+                .default_value("text"),
+            arg!(--"output-json" <FILE> "Writes the results as JSON to FILE (same as -oJ)"),
+            arg!(--"output-xml" <FILE> "Writes the results as nmap XML to FILE (same as -oX)"),
+            arg!(--"output-grepable" <FILE> "Writes a line per host to FILE (same as -oG)"),
+            arg!(--"output-csv" <FILE> "Writes a row per port to FILE as CSV"),
+            arg!(--"output-all" <BASENAME> "Writes every format to BASENAME with its \
+                extension (same as -oA)"),
+            arg!(-p --port <PORT> "One or more ports or ranges separated by a comma (e.g. \
+                22,80,1-1024), where -p- selects every port")
+            .value_delimiter(',')
+            .allow_hyphen_values(true)
+            .conflicts_with("top-ports"),
+            arg!(--"top-ports" <N> "Scan the N most frequent ports (up to 100)")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..=100)),
+            arg!(--"exclude-ports" <PORT> "One or more ports or ranges to skip")
+                .value_delimiter(',')
+                .allow_hyphen_values(true),
+            arg!([target] ... "Addresses, hostnames, CIDR blocks (e.g. 10.0.0.0/24) or octet \
+                ranges (e.g. 10.0.0.1-50) to scan, separated by a comma or space")
+            .required(true),
+        ])
+        .args([
+            // Timing arguments.
+            arg!(-T --timing <LEVEL> "Timing template, from 0 (paranoid) to 5 (insane)")
+                .value_parser(RangedU64ValueParser::<u8>::new().range(0..=5))
+                .default_value("3"),
+            arg!(--timeout <MS> "Milliseconds to wait for the reply to a probe")
+                .value_parser(RangedU64ValueParser::<u64>::new().range(1..)),
+            arg!(--retries <N> "Times an unanswered SYN or UDP probe is sent again")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(0..=100)),
+            arg!(--"max-parallelism" <N> "Maximum number of probes in flight at once")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+            arg!(--"max-rate" <PPS> "Maximum packets per second sent by the whole scan")
+                .value_parser(RangedU64ValueParser::<u32>::new().range(1..)),
+            arg!(--"min-rate" <PPS> "Packets per second sent by SYN and UDP scans even if \
+                the congestion window is full")
+            .value_parser(RangedU64ValueParser::<u32>::new().range(1..)),
+            arg!(--"congestion-control" "Limits SYN and UDP probes in flight to a window that \
+                shrinks as probes go unanswered")
+            .action(ArgAction::SetTrue),
         ])
         .args([
             // Scan techniques.
             arg!(-t --tcp "TCP scan").action(ArgAction::SetTrue),
             arg!(-s --syn "SYN scan").action(ArgAction::SetTrue),
+            arg!(-u --udp "UDP scan").action(ArgAction::SetTrue),
+            arg!(--fin "FIN scan, where ports that don't reset are open or filtered (same as \
+                -sF)")
+            .action(ArgAction::SetTrue),
+            arg!(--null "NULL scan, which sends no TCP flag at all (same as -sN)")
+                .action(ArgAction::SetTrue),
+            arg!(--xmas "Xmas scan, which sends the FIN, PSH and URG flags (same as -sX)")
+                .action(ArgAction::SetTrue),
+            arg!(--ack "ACK scan, which tells filtered ports from unfiltered ones rather than \
+                open from closed (same as -sA)")
+            .action(ArgAction::SetTrue),
+            arg!(--window "Window scan, an ACK scan telling open ports by the window of their \
+                resets on some systems (same as -sW)")
+            .action(ArgAction::SetTrue),
+            arg!(--maimon "Maimon scan, a FIN scan sending the FIN and ACK flags (same as -sM)")
+                .action(ArgAction::SetTrue),
+            arg!(--scanflags <FLAGS> "Scan sending custom TCP flags, named (e.g. SYNFIN or \
+                URGACKPSHRSTSYNFIN) or in hex (e.g. 0x29)"),
+            arg!(--"scanflags-as" <STYLE> "Reads the replies to custom flags as the SYN scan \
+                or the FIN scan does")
+            .value_parser(["syn", "fin"])
+            .default_value("syn")
+            .requires("scanflags"),
+            arg!(-S --stateless "Stateless SYN scan, which sends every probe up front and \
+                suits large address ranges")
+            .action(ArgAction::SetTrue),
+            arg!(--rate <PPS> "Packets per second sent by the stateless SYN scan \
+                [default: 10000]")
+            .value_parser(RangedU64ValueParser::<u32>::new().range(1..))
+            .requires("stateless"),
+        ])
+        .args([
+            // Banner grabbing arguments.
+            arg!(--banner "Reads what open ports send once connected by the TCP scan")
+                .action(ArgAction::SetTrue)
+                .requires("tcp"),
+            arg!(--"banner-wait" <MS> "Milliseconds to read the banner for [default: 1000]")
+                .value_parser(RangedU64ValueParser::<u64>::new().range(1..))
+                .requires("banner"),
+            arg!(--"banner-size" <BYTES> "Most bytes kept of a banner [default: 512]")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .requires("banner"),
+            arg!(--"banner-probe" <PAYLOAD> "Sent before reading the banner, for services \
+                that wait for the client (e.g. $'HEAD / HTTP/1.0\\r\\n\\r\\n' in bash)")
+            .requires("banner"),
         ])
         .group(
             ArgGroup::new("techniques")
-                .args(["tcp", "syn"])
+                .args([
+                    "tcp",
+                    "syn",
+                    "udp",
+                    "stateless",
+                    "fin",
+                    "null",
+                    "xmas",
+                    "ack",
+                    "window",
+                    "maimon",
+                    "scanflags",
+                ])
                 .multiple(true)
                 .required(true),
         )
-        .get_matches();
+        .get_matches_from(expand_nmap_flags(args.iter().cloned()));
 
     // Extract arguments.
     let parsed = parse_args(arg_matches)?;
@@ -108,14 +439,100 @@ fn main() -> Result<()> {
         logger::init();
     }
 
-    // Parse target.
-    let ip = resolver::lookup(&parsed.target)?;
-
-    // Start scanner.
-    let result = Scanner::new(ip, parsed.ports, parsed.techniques).start();
+    // Resolve targets and start scanner.
+    let scanner = parsed.scanner.build()?;
+    let result = match parsed.output {
+        Some(_) => scanner.start()?,
+        // Open ports are shown as found, before the table.
+        None => scanner.run(|ip, pr| {
+            if pr.state == PortState::Open {
+                println!("Discovered open port {}/{} on {}", pr.port, pr.protocol, ip);
+            }
+        })?,
+    };
 
     // Show result.
-    print_results(result);
+    let report = scanner.report(&args, &result);
+    match parsed.output {
+        Some(format) => format.write(&report, &mut io::stdout().lock())?,
+        None => print_results(&result, parsed.show_closed),
+    }
+
+    for (format, path) in &parsed.files {
+        File::create(path)
+            .and_then(|mut file| format.write(&report, &mut file))
+            .map_err(|e| ScanError::OutputFailed(path.clone(), e.into()))?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_single_port() {
+        assert_eq!(parse_port_range("80").unwrap(), 80..=80);
+        assert!(parse_port_range("65536").is_err());
+        assert!(parse_port_range("").is_err());
+    }
+
+    #[test]
+    fn parse_port_ranges() {
+        assert_eq!(parse_port_range("1-1024").unwrap(), 1..=1024);
+        assert_eq!(parse_port_range("-100").unwrap(), 1..=100);
+        assert_eq!(parse_port_range("60000-").unwrap(), 60000..=u16::MAX);
+        assert_eq!(parse_port_range("-").unwrap(), 1..=u16::MAX);
+    }
+
+    #[test]
+    fn parse_invalid_port_ranges() {
+        assert!(matches!(
+            parse_port_range("5-3"),
+            Err(ScanError::InvalidPort(_))
+        ));
+        assert!(parse_port_range("a-3").is_err());
+        assert!(parse_port_range("1-2-3").is_err());
+    }
+
+    #[test]
+    fn parse_port_zero() {
+        assert!(matches!(
+            parse_port_range("0"),
+            Err(ScanError::InvalidPort(_))
+        ));
+        assert!(parse_port_range("0-").is_err());
+        assert!(parse_port_range("0-80").is_err());
+    }
+
+    #[test]
+    fn expand_nmap_output_flags() {
+        let args = ["pscan", "-oJ", "out.json", "-s", "-sV", "-oJson"].map(String::from);
+
+        assert_eq!(
+            expand_nmap_flags(args.into_iter()),
+            [
+                "pscan",
+                "--output-json",
+                "out.json",
+                "-s",
+                "--service-version",
+                "-oJson"
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_overlapping_ranges() {
+        assert_eq!(
+            merge_port_ranges(vec![80..=80, 1..=100, 200..=300, 101..=101, 250..=400]),
+            [1..=101, 200..=400]
+        );
+        assert_eq!(merge_port_ranges(vec![22..=22, 22..=22]), [22..=22]);
+        assert_eq!(
+            merge_port_ranges(vec![443..=443, 22..=22]),
+            [22..=22, 443..=443]
+        );
+    }
+}
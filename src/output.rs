@@ -0,0 +1,119 @@
+//! Reports of a finished scan in formats meant for other tools, written to
+//! files or instead of the table printed by the binary.
+
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+};
+
+use crate::scan::{ScanOptions, ScanResult, ScanType};
+
+mod csv;
+mod grepable;
+mod json;
+mod xml;
+
+/// Everything known about a scan once it finished.
+#[derive(Debug)]
+pub struct Report<'a> {
+    /// Command line that started the scan.
+    pub args: &'a [String],
+    /// Targets as given by the user, before being resolved.
+    pub targets: &'a [String],
+    pub ips: &'a [IpAddr],
+    pub techniques: Vec<ScanType>,
+    pub options: &'a ScanOptions,
+    pub result: &'a ScanResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// Nmap's XML format.
+    Xml,
+    /// Nmap's grepable format.
+    Grepable,
+    Csv,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Json, Format::Xml, Format::Grepable, Format::Csv];
+
+    /// Name of the format, as taken by `--output`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Xml => "xml",
+            Format::Grepable => "grepable",
+            Format::Csv => "csv",
+        }
+    }
+
+    /// Extension of the files written in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Xml => "xml",
+            Format::Grepable => "gnmap",
+            Format::Csv => "csv",
+        }
+    }
+
+    pub fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Format::Json => json::write(report, out),
+            Format::Xml => xml::write(report, out),
+            Format::Grepable => grepable::write(report, out),
+            Format::Csv => csv::write(report, out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::scan::{HostResult, PortResult, PortState, Protocol, Reason};
+
+    use super::*;
+
+    /// Runs `f` with the report of a UDP scan that found one port.
+    pub fn with_sample<T>(f: impl FnOnce(&Report) -> T) -> T {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let result = ScanResult {
+            started: UNIX_EPOCH,
+            elapsed: Duration::from_millis(1500),
+            hosts: vec![HostResult {
+                ip,
+                ports: vec![PortResult {
+                    port: 53,
+                    protocol: Protocol::Udp,
+                    state: PortState::OpenFiltered,
+                    reason: Reason::NoResponse(2),
+                    kind: ScanType::Udp,
+                    elapsed: Duration::from_millis(6),
+                    error: None,
+                    service: None,
+                    banner: None,
+                    tls: None,
+                }],
+            }],
+        };
+
+        f(&Report {
+            args: &[
+                String::from("pscan"),
+                String::from("-u"),
+                String::from("10.0.0.1"),
+            ],
+            targets: &[String::from("10.0.0.1")],
+            ips: &[ip],
+            techniques: vec![ScanType::Udp],
+            options: &ScanOptions::default(),
+            result: &result,
+        })
+    }
+}
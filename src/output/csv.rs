@@ -0,0 +1,73 @@
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use crate::scan::TlsInfo;
+
+use super::Report;
+
+const HEADER: &str = "host,port,proto,state,method,reason,rtt_ms,banner,tls";
+
+/// Quotes `field` if it holds a comma or a quote, as RFC 4180 asks.
+fn quote(field: &str) -> Cow<'_, str> {
+    match field.contains([',', '"']) {
+        true => Cow::Owned(format!("\"{}\"", field.replace('"', "\"\""))),
+        false => Cow::Borrowed(field),
+    }
+}
+
+/// Writes a row per scanned port, closed ones included. Banners and TLS
+/// summaries are the only fields that might need quotes, being what the
+/// ports sent.
+pub fn write(report: &Report, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", HEADER)?;
+
+    for hr in &report.result.hosts {
+        for pr in &hr.ports {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{:.3},{},{}",
+                hr.ip,
+                pr.port,
+                pr.protocol,
+                pr.state,
+                pr.kind.name(),
+                pr.reason,
+                pr.elapsed.as_secs_f64() * 1000.0,
+                quote(pr.banner.as_deref().unwrap_or_default()),
+                quote(&pr.tls.as_ref().map(TlsInfo::summary).unwrap_or_default()),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::tests::with_sample;
+
+    use super::*;
+
+    #[test]
+    fn header_and_rows() {
+        let csv = with_sample(|report| {
+            let mut out = vec![];
+            write(report, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        });
+
+        assert_eq!(
+            csv,
+            "host,port,proto,state,method,reason,rtt_ms,banner,tls\n\
+            10.0.0.1,53,udp,open|filtered,udp,no-response (2 retries),6.000,,\n"
+        );
+    }
+
+    #[test]
+    fn banners_are_quoted() {
+        assert_eq!(quote("SSH-2.0-OpenSSH_9.6"), "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(quote(r#"a, "b""#), r#""a, ""b""""#);
+    }
+}
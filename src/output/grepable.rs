@@ -0,0 +1,99 @@
+//! One line per host, as nmap's `-oG`, meant to be filtered with grep or
+//! awk.
+
+use std::io::{self, Write};
+
+use crate::scan::PortState;
+
+use super::Report;
+
+pub fn write(report: &Report, out: &mut dyn Write) -> io::Result<()> {
+    let result = report.result;
+
+    writeln!(
+        out,
+        "# {} {} scan initiated {} as: {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        humantime::format_rfc3339_seconds(result.started),
+        report.args.join(" "),
+    )?;
+
+    for hr in &result.hosts {
+        // Fields are port/state/protocol/owner/service/rpc info/version.
+        let ports = hr
+            .ports
+            .iter()
+            .filter(|pr| pr.state != PortState::Closed)
+            .map(|pr| {
+                // Banners are the closest to a version of unknown services.
+                let (mut service, mut version) = match (&pr.service, &pr.banner) {
+                    (Some(service), _) => (service.name.clone(), service.description()),
+                    (None, Some(banner)) => (String::new(), banner.clone()),
+                    (None, None) => Default::default(),
+                };
+                // Services over TLS are named as nmap does (e.g. `ssl|http`).
+                if let Some(tls) = &pr.tls {
+                    service = match service.is_empty() {
+                        true => String::from("ssl"),
+                        false => format!("ssl|{}", service),
+                    };
+                    version = [version, tls.summary()]
+                        .into_iter()
+                        .filter(|v| !v.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                }
+                // Slashes in the service would split its fields.
+                format!(
+                    "{}/{}/{}//{}//{}",
+                    pr.port,
+                    pr.state,
+                    pr.protocol,
+                    service.replace('/', "|"),
+                    version.replace('/', "|"),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        write!(out, "Host: {} ()\tPorts: {}", hr.ip, ports)?;
+        match hr.closed() {
+            0 => writeln!(out)?,
+            closed => writeln!(out, "\tIgnored State: closed ({})", closed)?,
+        }
+    }
+
+    writeln!(
+        out,
+        "# {} done at {} -- {} IP addresses scanned in {:.2} seconds",
+        env!("CARGO_PKG_NAME"),
+        humantime::format_rfc3339_seconds(result.finished()),
+        result.hosts.len(),
+        result.elapsed.as_secs_f64(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::tests::with_sample;
+
+    use super::*;
+
+    #[test]
+    fn one_line_per_host() {
+        let lines = with_sample(|report| {
+            let mut out = vec![];
+            write(report, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        });
+        let lines = lines.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "Host: 10.0.0.1 ()\tPorts: 53/open|filtered/udp////"
+        );
+        assert!(lines[2].starts_with("# pscan done at 1970-01-01T00:00:01Z"));
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+use crate::scan::{ser, HostResult, ScanOptions, ScanType};
+
+use super::Report;
+
+#[derive(Serialize)]
+struct Scanner {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    scanner: Scanner,
+    #[serde(serialize_with = "ser::rfc3339")]
+    start: SystemTime,
+    #[serde(serialize_with = "ser::rfc3339")]
+    end: SystemTime,
+    targets: &'a [String],
+    ips: &'a [IpAddr],
+    techniques: &'a [ScanType],
+    options: &'a ScanOptions,
+    hosts: &'a [HostResult],
+}
+
+pub fn write(report: &Report, out: &mut dyn Write) -> io::Result<()> {
+    let document = Document {
+        scanner: Scanner {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        },
+        start: report.result.started,
+        end: report.result.finished(),
+        targets: report.targets,
+        ips: report.ips,
+        techniques: &report.techniques,
+        options: report.options,
+        hosts: &report.result.hosts,
+    };
+
+    serde_json::to_writer_pretty(&mut *out, &document)?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::tests::with_sample;
+
+    use super::*;
+
+    #[test]
+    fn document_layout() {
+        let document = with_sample(|report| {
+            let mut out = vec![];
+            write(report, &mut out).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&out).unwrap()
+        });
+
+        assert_eq!(document["end"], "1970-01-01T00:00:01.500Z");
+        assert_eq!(document["techniques"][0], "udp");
+        assert_eq!(
+            document["hosts"][0]["ports"][0],
+            serde_json::json!({
+                "port": 53,
+                "protocol": "udp",
+                "state": "open|filtered",
+                "reason": { "no-response": 2 },
+                "method": "udp",
+                "elapsed_ms": 6.0,
+            })
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Output following nmap's DTD (https://nmap.org/book/nmap-dtd.html), so
+//! that tools importing nmap XML accept it.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    io::{self, Write},
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::scan::{FlagsStyle, PortResult, PortState, Reason, ScanType, Service};
+
+use super::Report;
+
+// Version of nmap's output format being followed.
+const XML_OUTPUT_VERSION: &str = "1.05";
+
+fn escape(raw: &str) -> Cow<'_, str> {
+    if !raw.contains(['&', '<', '>', '"', '\'', '\n']) {
+        return Cow::Borrowed(raw);
+    }
+
+    let mut escaped = String::with_capacity(raw.len() + 8);
+    raw.chars().for_each(|c| match c {
+        '&' => escaped.push_str("&amp;"),
+        '<' => escaped.push_str("&lt;"),
+        '>' => escaped.push_str("&gt;"),
+        '"' => escaped.push_str("&quot;"),
+        '\'' => escaped.push_str("&apos;"),
+        // Attributes would turn it into a space otherwise.
+        '\n' => escaped.push_str("&#xa;"),
+        c => escaped.push(c),
+    });
+
+    Cow::Owned(escaped)
+}
+
+#[inline]
+fn epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Name of the technique in nmap's `scaninfo` element.
+fn scan_type(kind: ScanType) -> &'static str {
+    match kind {
+        ScanType::Tcp => "connect",
+        ScanType::Syn | ScanType::StatelessSyn => "syn",
+        ScanType::Udp => "udp",
+        ScanType::Fin => "fin",
+        ScanType::Null => "null",
+        ScanType::Xmas => "xmas",
+        ScanType::Ack => "ack",
+        ScanType::Window => "window",
+        ScanType::Maimon => "maimon",
+        ScanType::Flags { style, .. } => match style {
+            FlagsStyle::Syn => "syn",
+            FlagsStyle::Fin => "fin",
+        },
+        // Not a type of nmap's, so readers show it as is.
+        ScanType::Custom { name, .. } => name,
+    }
+}
+
+/// Reason as nmap names it.
+fn reason(reason: Reason) -> &'static str {
+    match reason {
+        Reason::SynAck => "syn-ack",
+        Reason::Rst => "reset",
+        Reason::ConnRefused => "conn-refused",
+        Reason::UdpResponse => "udp-response",
+        Reason::IcmpPortUnreach => "port-unreach",
+        Reason::IcmpUnreach => "host-unreach",
+        Reason::TimeExceeded => "time-exceeded",
+        Reason::NoResponse(_) => "no-response",
+        Reason::Unsent => "unsent",
+        Reason::Error => "error",
+    }
+}
+
+/// Lists ports as nmap's `services` attribute, merging consecutive ones
+/// into ranges (e.g. `22,80-82`).
+fn services(ports: &BTreeSet<u16>) -> String {
+    let mut ranges: Vec<(u16, u16)> = vec![];
+    for &port in ports {
+        match ranges.last_mut() {
+            Some((_, end)) if end.checked_add(1) == Some(port) => *end = port,
+            _ => ranges.push((port, port)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// nmap's `service` element, whose attributes are only set if known.
+fn service(service: &Service) -> String {
+    let attributes = [
+        ("product", &service.product),
+        ("version", &service.version),
+        ("extrainfo", &service.info),
+        ("hostname", &service.hostname),
+        ("ostype", &service.os),
+        ("devicetype", &service.device),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some(format!(r#" {}="{}""#, name, escape(value.as_ref()?))))
+    .collect::<String>();
+
+    format!(
+        r#"<service name="{}"{} method="probed" conf="10"/>"#,
+        escape(&service.name),
+        attributes
+    )
+}
+
+/// Banner and TLS of the port as the output of nmap's scripts, which
+/// readers show as they are.
+fn scripts(pr: &PortResult) -> String {
+    let mut scripts = vec![];
+    if let Some(banner) = &pr.banner {
+        scripts.push(("banner", banner.clone()));
+    }
+    if let Some(tls) = &pr.tls {
+        scripts.push((
+            "tls-handshake",
+            format!("Protocol: {}\nCipher: {}", tls.version, tls.cipher),
+        ));
+        if let Some(cert) = &tls.certificate {
+            scripts.push((
+                "ssl-cert",
+                format!(
+                    "Subject: {}\nSubject Alternative Name: {}\nIssuer: {}\n\
+                    Not valid before: {}\nNot valid after:  {}",
+                    cert.subject,
+                    cert.alt_names.join(", "),
+                    cert.issuer,
+                    humantime::format_rfc3339_seconds(cert.not_before),
+                    humantime::format_rfc3339_seconds(cert.not_after),
+                ),
+            ));
+        }
+    }
+
+    scripts
+        .into_iter()
+        .map(|(id, output)| format!(r#"<script id="{}" output="{}"/>"#, id, escape(&output)))
+        .collect()
+}
+
+pub fn write(report: &Report, out: &mut dyn Write) -> io::Result<()> {
+    let result = report.result;
+    let (start, end) = (epoch(result.started), epoch(result.finished()));
+    let startstr = humantime::format_rfc3339_seconds(result.started);
+    let endstr = humantime::format_rfc3339_seconds(result.finished());
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<!DOCTYPE nmaprun>")?;
+    writeln!(
+        out,
+        r#"<nmaprun scanner="{}" args="{}" start="{}" startstr="{}" version="{}" xmloutputversion="{}">"#,
+        env!("CARGO_PKG_NAME"),
+        escape(&report.args.join(" ")),
+        start,
+        startstr,
+        env!("CARGO_PKG_VERSION"),
+        XML_OUTPUT_VERSION,
+    )?;
+
+    for &kind in &report.techniques {
+        let ports = result
+            .hosts
+            .iter()
+            .flat_map(|hr| hr.ports.iter())
+            .filter(|pr| pr.kind.protocol() == kind.protocol())
+            .map(|pr| pr.port)
+            .collect::<BTreeSet<_>>();
+
+        // Custom flags are told apart as nmap's `--scanflags` does.
+        let flags = match kind {
+            ScanType::Flags { flags, .. } => format!(r#" scanflags="{}""#, flags),
+            _ => String::new(),
+        };
+
+        writeln!(
+            out,
+            r#"<scaninfo type="{}"{} protocol="{}" numservices="{}" services="{}"/>"#,
+            scan_type(kind),
+            flags,
+            kind.protocol(),
+            ports.len(),
+            services(&ports),
+        )?;
+    }
+    writeln!(out, r#"<verbose level="0"/>"#)?;
+    writeln!(out, r#"<debugging level="0"/>"#)?;
+
+    for hr in &result.hosts {
+        // Hosts aren't discovered before being scanned, as nmap's `-Pn`.
+        writeln!(out, r#"<host starttime="{}" endtime="{}">"#, start, end)?;
+        writeln!(
+            out,
+            r#"<status state="up" reason="user-set" reason_ttl="0"/>"#
+        )?;
+        writeln!(
+            out,
+            r#"<address addr="{}" addrtype="{}"/>"#,
+            hr.ip,
+            match hr.ip {
+                IpAddr::V4(_) => "ipv4",
+                IpAddr::V6(_) => "ipv6",
+            }
+        )?;
+        writeln!(out, "<hostnames>\n</hostnames>")?;
+        writeln!(out, "<ports>")?;
+        for pr in &hr.ports {
+            writeln!(
+                out,
+                r#"<port protocol="{}" portid="{}"><state state="{}" reason="{}" reason_ttl="0"/>{}{}</port>"#,
+                pr.protocol,
+                pr.port,
+                pr.state,
+                reason(pr.reason),
+                pr.service.as_ref().map(service).unwrap_or_default(),
+                scripts(pr),
+            )?;
+        }
+        writeln!(out, "</ports>")?;
+        writeln!(out, "</host>")?;
+    }
+
+    let up = result
+        .hosts
+        .iter()
+        .filter(|hr| hr.ports.iter().any(|pr| pr.state != PortState::Unknown))
+        .count();
+    let total = result.hosts.len();
+    let elapsed = result.elapsed.as_secs_f64();
+
+    writeln!(out, "<runstats>")?;
+    writeln!(
+        out,
+        r#"<finished time="{}" timestr="{}" elapsed="{:.2}" summary="{} done at {}; {} IP addresses ({} hosts up) scanned in {:.2} seconds" exit="success"/>"#,
+        end,
+        endstr,
+        elapsed,
+        env!("CARGO_PKG_NAME"),
+        endstr,
+        total,
+        up,
+        elapsed,
+    )?;
+    writeln!(
+        out,
+        r#"<hosts up="{}" down="{}" total="{}"/>"#,
+        up,
+        total - up,
+        total
+    )?;
+    writeln!(out, "</runstats>")?;
+    writeln!(out, "</nmaprun>")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::tests::with_sample;
+
+    use super::*;
+
+    #[test]
+    fn special_chars_are_escaped() {
+        assert_eq!(escape("-p 80"), "-p 80");
+        assert_eq!(escape(r#"a<b & "c""#), "a&lt;b &amp; &quot;c&quot;");
+        assert_eq!(escape("a\nb"), "a&#xa;b");
+    }
+
+    #[test]
+    fn services_are_merged_into_ranges() {
+        let ports = BTreeSet::from([22, 80, 81, 82, 443, 65535]);
+
+        assert_eq!(services(&ports), "22,80-82,443,65535");
+        assert_eq!(services(&BTreeSet::new()), "");
+    }
+
+    #[test]
+    fn known_service_fields_are_attributes() {
+        let ssh = Service {
+            name: String::from("ssh"),
+            product: Some(String::from("OpenSSH")),
+            version: Some(String::from("9.6p1")),
+            info: Some(String::from("protocol 2.0")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            service(&ssh),
+            r#"<service name="ssh" product="OpenSSH" version="9.6p1" extrainfo="protocol 2.0" method="probed" conf="10"/>"#
+        );
+    }
+
+    #[test]
+    fn document_layout() {
+        let xml = with_sample(|report| {
+            let mut out = vec![];
+            write(report, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        });
+
+        assert!(
+            xml.contains(r#"<scaninfo type="udp" protocol="udp" numservices="1" services="53"/>"#)
+        );
+        assert!(xml.contains(r#"<address addr="10.0.0.1" addrtype="ipv4"/>"#));
+        assert!(xml.contains(
+            r#"<port protocol="udp" portid="53"><state state="open|filtered" reason="no-response" reason_ttl="0"/></port>"#
+        ));
+        assert!(xml.contains(r#"<hosts up="1" down="0" total="1"/>"#));
+        assert!(xml.ends_with("</nmaprun>\n"));
+    }
+}
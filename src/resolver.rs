@@ -1,30 +1,280 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 
+use pnet::ipnetwork::IpNetwork;
 use url::Host;
 
 use crate::error::ScanError;
 
-pub fn lookup(target: &str) -> Result<Ipv4Addr, ScanError> {
-    let ip = match Host::parse(target).map_err(ScanError::HostParseFailed)? {
-        Host::Domain(dmn) => (dmn, 0 /* dummy port */)
-            .to_socket_addrs()
-            .map_err(ScanError::ResolverFailed)?
-            .find_map(|saddr| match saddr {
-                SocketAddr::V4(sip) => {
-                    let ip = *sip.ip();
+// Most hosts a single scan can target, the size of an IPv4 /16 or an IPv6 /112.
+const MAX_HOSTS: usize = 1 << 16;
 
-                    log::debug!("Found IPv4 `{}` mapped by target `{}`", ip, target);
+/// Resolves a single address or hostname. Hostnames are resolved to an
+/// IPv4 address (A record) or, if `ipv6` is set, to an IPv6 address
+/// (AAAA record).
+pub fn lookup(target: &str, ipv6: bool) -> Result<IpAddr, ScanError> {
+    // IPv6 addresses might come enclosed in brackets or not.
+    let target = target.trim_start_matches('[').trim_end_matches(']');
+    let bracketed;
+    let host = match target.contains(':') {
+        true => {
+            bracketed = format!("[{}]", target);
+            &bracketed
+        }
+        false => target,
+    };
 
-                    Some(ip)
-                }
-                SocketAddr::V6(_) => None,
-            })
+    let ip = match Host::parse(host).map_err(ScanError::HostParseFailed)? {
+        Host::Domain(dmn) => (dmn, 0 /* dummy port */)
+            .to_socket_addrs()
+            .map_err(|e| ScanError::ResolverFailed(e.into()))?
+            .map(|saddr| saddr.ip())
+            .find(|ip| ip.is_ipv6() == ipv6)
+            .inspect(|ip| log::debug!("Found IP `{}` mapped by target `{}`", ip, target))
             .ok_or(ScanError::DomainLookupFailed(target.into()))?,
-        Host::Ipv4(ip) => ip,
-        Host::Ipv6(_) => Err(ScanError::OnlyIpv4TargetSupported)?,
+        Host::Ipv4(ip) => IpAddr::V4(ip),
+        Host::Ipv6(ip) => IpAddr::V6(ip),
     };
 
     Ok(ip)
 }
+
+/// Expands each target into the list of hosts it represents.
+///
+/// Besides addresses and hostnames, each target can be written in CIDR
+/// notation (e.g. `10.0.0.0/24`), with octet ranges (e.g. `10.0.0.1-50`
+/// or `10.0.1-3.*`) or as a comma separated list of any of those.
+/// Duplicated hosts are only returned once. Targets can't map more than
+/// 65536 hosts altogether.
+pub fn expand<S: AsRef<str>>(targets: &[S], ipv6: bool) -> Result<Vec<IpAddr>, ScanError> {
+    let mut hosts = vec![];
+    let mut seen = HashSet::new();
+
+    for target in targets
+        .iter()
+        .flat_map(|t| t.as_ref().split(','))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+    {
+        let expanded = if let Some((base, prefix)) = target.split_once('/') {
+            expand_cidr(target, base, prefix, ipv6)?
+        } else if is_octet_range(target) {
+            expand_octet_range(target)?
+        } else {
+            vec![lookup(target, ipv6)?]
+        };
+
+        log::debug!("Target `{}` maps {} host(s)", target, expanded.len());
+
+        hosts.extend(expanded.into_iter().filter(|&ip| seen.insert(ip)));
+        if hosts.len() > MAX_HOSTS {
+            return Err(ScanError::TooManyHosts(targets_to_string(targets)));
+        }
+    }
+
+    if hosts.is_empty() {
+        return Err(ScanError::InvalidTarget(targets_to_string(targets)));
+    }
+
+    Ok(hosts)
+}
+
+fn targets_to_string<S: AsRef<str>>(targets: &[S]) -> String {
+    targets
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn expand_cidr(
+    target: &str,
+    base: &str,
+    prefix: &str,
+    ipv6: bool,
+) -> Result<Vec<IpAddr>, ScanError> {
+    let invalid = || ScanError::InvalidTarget(target.into());
+
+    let prefix = prefix.parse::<u8>().map_err(|_| invalid())?;
+    let ip = lookup(base, ipv6)?;
+
+    let network = IpNetwork::new(ip, prefix).map_err(|_| invalid())?;
+
+    let host_bits = match network {
+        IpNetwork::V4(_) => 32,
+        IpNetwork::V6(_) => 128,
+    } - prefix as u32;
+    if host_bits > MAX_HOSTS.ilog2() {
+        return Err(ScanError::TooManyHosts(target.into()));
+    }
+
+    Ok(match network {
+        IpNetwork::V4(network) => network.iter().map(IpAddr::V4).collect(),
+        IpNetwork::V6(network) => network.iter().map(IpAddr::V6).collect(),
+    })
+}
+
+fn is_octet_range(target: &str) -> bool {
+    let octets = target.split('.').collect::<Vec<_>>();
+
+    octets.len() == 4
+        && octets.iter().any(|o| o.contains(['-', '*']))
+        && octets.iter().all(|o| {
+            o.chars()
+                .all(|c| c.is_ascii_digit() || c == '-' || c == '*')
+        })
+}
+
+fn parse_octet(target: &str, raw: &str) -> Result<(u8, u8), ScanError> {
+    let invalid = || ScanError::InvalidTarget(target.into());
+    let parse = |o: &str| o.parse::<u8>().map_err(|_| invalid());
+
+    let (start, end) = match raw {
+        "*" => (u8::MIN, u8::MAX),
+        _ => match raw.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => {
+                let octet = parse(raw)?;
+                (octet, octet)
+            }
+        },
+    };
+
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok((start, end))
+}
+
+fn expand_octet_range(target: &str) -> Result<Vec<IpAddr>, ScanError> {
+    let ranges = target
+        .split('.')
+        .map(|o| parse_octet(target, o))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let size = ranges
+        .iter()
+        .map(|(start, end)| (end - start) as usize + 1)
+        .product::<usize>();
+    if size > MAX_HOSTS {
+        return Err(ScanError::TooManyHosts(target.into()));
+    }
+
+    let mut hosts = Vec::with_capacity(size);
+    for a in ranges[0].0..=ranges[0].1 {
+        for b in ranges[1].0..=ranges[1].1 {
+            for c in ranges[2].0..=ranges[2].1 {
+                for d in ranges[3].0..=ranges[3].1 {
+                    hosts.push(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+                }
+            }
+        }
+    }
+
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    #[test]
+    fn expand_single_address() {
+        assert_eq!(expand(&["10.0.0.1"], false).unwrap(), [v4(10, 0, 0, 1)]);
+    }
+
+    #[test]
+    fn expand_cidr_block() {
+        assert_eq!(
+            expand(&["10.0.0.0/30"], false).unwrap(),
+            [
+                v4(10, 0, 0, 0),
+                v4(10, 0, 0, 1),
+                v4(10, 0, 0, 2),
+                v4(10, 0, 0, 3)
+            ]
+        );
+        assert_eq!(expand(&["10.0.0.0/24"], false).unwrap().len(), 256);
+    }
+
+    #[test]
+    fn expand_ipv6_cidr_block() {
+        assert_eq!(expand(&["fd00::/126"], false).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn expand_last_octet_range() {
+        let hosts = expand(&["10.0.0.1-50"], false).unwrap();
+
+        assert_eq!(hosts.len(), 50);
+        assert_eq!(hosts.first(), Some(&v4(10, 0, 0, 1)));
+        assert_eq!(hosts.last(), Some(&v4(10, 0, 0, 50)));
+    }
+
+    #[test]
+    fn expand_several_octet_ranges() {
+        let hosts = expand(&["10.0.1-3.*"], false).unwrap();
+
+        assert_eq!(hosts.len(), 3 * 256);
+        assert_eq!(hosts.first(), Some(&v4(10, 0, 1, 0)));
+        assert_eq!(hosts.last(), Some(&v4(10, 0, 3, 255)));
+    }
+
+    #[test]
+    fn expand_comma_list_and_positional_targets() {
+        assert_eq!(
+            expand(&["10.0.0.1,10.0.0.2", "10.0.0.3"], false).unwrap(),
+            [v4(10, 0, 0, 1), v4(10, 0, 0, 2), v4(10, 0, 0, 3)]
+        );
+    }
+
+    #[test]
+    fn expand_removes_duplicates() {
+        assert_eq!(
+            expand(&["10.0.0.1,10.0.0.0/31", "10.0.0.1"], false).unwrap(),
+            [v4(10, 0, 0, 1), v4(10, 0, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn expand_rejects_invalid_ranges() {
+        for target in ["10.0.0.1-", "10.0.0.5-3", "10.0.0.256-300", "10.0.0.0/33"] {
+            assert!(
+                matches!(expand(&[target], false), Err(ScanError::InvalidTarget(_))),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn expand_rejects_too_many_hosts() {
+        for target in ["0.0.0.0/0", "10.0.0.0/15", "*.*.*.*", "fd00::/111"] {
+            assert!(
+                matches!(expand(&[target], false), Err(ScanError::TooManyHosts(_))),
+                "{}",
+                target
+            );
+        }
+        assert!(matches!(
+            expand(&["10.0.0.0/16", "10.1.0.0/31"], false),
+            Err(ScanError::TooManyHosts(_))
+        ));
+    }
+
+    #[test]
+    fn parse_octet_forms() {
+        assert_eq!(parse_octet("", "*").unwrap(), (0, 255));
+        assert_eq!(parse_octet("", "7").unwrap(), (7, 7));
+        assert_eq!(parse_octet("", "1-50").unwrap(), (1, 50));
+        assert!(parse_octet("", "1-").is_err());
+        assert!(parse_octet("", "5-3").is_err());
+    }
+}
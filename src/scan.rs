@@ -1,28 +1,68 @@
 use std::{
     fmt::{Debug, Display},
-    net::{Ipv4Addr, SocketAddrV4},
-    time::{Duration, Instant},
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use pnet::packet::tcp::TcpFlags;
+use rayon::{
+    iter::Either,
+    prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
+use serde::Serialize;
+
+use crate::{
+    error::ScanError,
+    output::{Format, Report},
+};
 
 use self::{
-    method::{SynScan, TcpScan},
-    port::COMMON_PORTS,
+    method::{AckScan, FinScan, StatelessSynScan, SynScan, TcpScan, UdpScan},
+    port::{COMMON_PORTS, TOP_PORTS},
+};
+
+pub use self::{
+    banner::BannerGrab,
+    builder::ScannerBuilder,
+    service::Service,
+    tls::{Certificate, TlsInfo},
 };
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod banner;
+mod builder;
 mod channel;
+mod engine;
 mod interface;
 mod method;
+mod ndp;
 mod pckt;
 mod port;
+mod rate;
+mod rtt;
+pub(crate) mod ser;
+mod service;
+mod tls;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PortState {
     Open,
     Filtered,
+    #[serde(rename = "open|filtered")]
+    OpenFiltered,
+    /// Reachable through any firewall, whether or not something listens.
+    Unfiltered,
     Unknown,
-    _Closed, // Closed ports aren't exposed.
+    Closed,
 }
 
 impl Display for PortState {
@@ -33,33 +73,369 @@ impl Display for PortState {
             match self {
                 PortState::Open => "open",
                 PortState::Filtered => "filtered",
+                PortState::OpenFiltered => "open|filtered",
+                PortState::Unfiltered => "unfiltered",
                 PortState::Unknown => "unknown",
-                PortState::_Closed => unreachable!(),
+                PortState::Closed => "closed",
             }
         )
     }
 }
 
-trait Executor: Debug + Sync {
-    fn scan(&self, addr: &SocketAddrV4) -> PortState;
+/// Evidence behind the state of a port, named as nmap's `--reason` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    SynAck,
+    Rst,
+    ConnRefused,
+    UdpResponse,
+    IcmpPortUnreach,
+    /// Any other destination unreachable error.
+    IcmpUnreach,
+    TimeExceeded,
+    /// Nothing arrived after the given number of retries.
+    NoResponse(usize),
+    /// The probe couldn't be sent.
+    Unsent,
+    /// Scanning the port failed, as recorded in its result.
+    Error,
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::SynAck => write!(f, "syn-ack"),
+            Reason::Rst => write!(f, "rst"),
+            Reason::ConnRefused => write!(f, "conn-refused"),
+            Reason::UdpResponse => write!(f, "udp-response"),
+            Reason::IcmpPortUnreach => write!(f, "icmp-port-unreach"),
+            Reason::IcmpUnreach => write!(f, "icmp-unreach"),
+            Reason::TimeExceeded => write!(f, "time-exceeded"),
+            Reason::NoResponse(0) => write!(f, "no-response"),
+            Reason::NoResponse(retries) => write!(f, "no-response ({} retries)", retries),
+            Reason::Unsent => write!(f, "unsent"),
+            Reason::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// State of a scanned port and its reason, or why it couldn't be scanned.
+pub type Verdict = Result<(PortState, Reason), ScanError>;
+
+/// State of a scanned port and its reason, which might still depend on a
+/// reply in flight.
+pub enum Probe {
+    /// The verdict is known already.
+    Done(Verdict),
+    /// The probe was sent and the closure waits for its reply. Every probe
+    /// of a host is sent before the first one is waited for, unless the
+    /// scan limits its parallelism.
+    Waiting(Box<dyn FnOnce() -> Verdict + Send>),
+}
+
+impl Probe {
+    /// Blocks until the verdict is known.
+    pub fn wait(self) -> Verdict {
+        match self {
+            Probe::Done(verdict) => verdict,
+            Probe::Waiting(waiter) => waiter(),
+        }
+    }
+}
+
+impl From<(PortState, Reason)> for Probe {
+    fn from(verdict: (PortState, Reason)) -> Self {
+        Probe::Done(Ok(verdict))
+    }
+}
+
+impl From<ScanError> for Probe {
+    fn from(e: ScanError) -> Self {
+        Probe::Done(Err(e))
+    }
+}
+
+/// Way of probing ports, which the scanner drives like the built-in
+/// techniques: its results are collected and streamed the same way, and
+/// the ports it finds closed are handed to the next technique.
+///
+/// Probes may be sent from [`Executor::scan`] and waited for in the
+/// returned [`Probe::Waiting`], so that replies are awaited concurrently
+/// without a thread per port.
+///
+/// ```no_run
+/// use std::net::{IpAddr, SocketAddr, TcpStream};
+///
+/// use pscan::scan::{
+///     Executor, PortState, PortsToScan, Probe, Protocol, Reason, ScanOptions, Scanner,
+/// };
+///
+/// #[derive(Debug)]
+/// struct Health;
+///
+/// impl Executor for Health {
+///     fn scan(&self, addr: &SocketAddr, _options: &ScanOptions) -> Probe {
+///         match TcpStream::connect(addr) {
+///             Ok(_) => (PortState::Open, Reason::SynAck).into(),
+///             Err(_) => (PortState::Closed, Reason::ConnRefused).into(),
+///         }
+///     }
+/// }
+///
+/// let mut scanner = Scanner::new(
+///     vec![IpAddr::from([127, 0, 0, 1])],
+///     PortsToScan::Selected(vec![8080]),
+///     vec![],
+///     ScanOptions::default(),
+/// );
+/// scanner.register("health", Protocol::Tcp, Health);
+/// let result = scanner.start().unwrap();
+/// ```
+pub trait Executor: Debug + Send + Sync {
+    /// Runs once before any port is scanned, for techniques that probe
+    /// every target up front.
+    fn prepare(
+        &self,
+        _ips: &[IpAddr],
+        _ports: &PortsToScan,
+        _options: &ScanOptions,
+    ) -> Result<(), ScanError> {
+        Ok(())
+    }
+
+    /// Probes `addr` without waiting for the reply if possible, so that
+    /// every port of a host can be in flight at once.
+    fn scan(&self, addr: &SocketAddr, options: &ScanOptions) -> Probe;
+
+    /// Takes what the probe of `addr` read from it, for techniques that
+    /// grab banners as [`ScanOptions::banner`] asks. Called once the
+    /// verdict of `addr` is known.
+    fn banner(&self, _addr: &SocketAddr) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Timing of a scan and what its probes read. Unset values fall back to
+/// the defaults of each technique.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanOptions {
+    /// How long to wait for the reply to a probe.
+    #[serde(rename = "timeout_ms", serialize_with = "ser::opt_millis")]
+    pub timeout: Option<Duration>,
+    /// How many times an unanswered raw probe is sent again.
+    pub retries: Option<usize>,
+    /// Maximum number of probes in flight at once.
+    pub max_parallelism: Option<usize>,
+    /// Packets per second that the whole scan never exceeds.
+    pub max_rate: Option<u32>,
+    /// Packets per second sent by raw techniques even if the congestion
+    /// window is full.
+    pub min_rate: Option<u32>,
+    /// Whether raw probes in flight are limited by a congestion window,
+    /// which shrinks as probes go unanswered.
+    pub congestion_control: bool,
+    /// How the TCP technique reads the banner of open ports, if at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<BannerGrab>,
+}
+
+impl ScanOptions {
+    /// Timing templates from paranoid (0) to insane (5), based on
+    /// https://nmap.org/book/performance-timing-templates.html, where 3 keeps
+    /// the defaults. Delays between probes aren't applied.
+    pub fn template(level: u8) -> Self {
+        let (timeout, retries, max_parallelism) = match level {
+            0 => (Some(300_000), Some(10), Some(1)),
+            1 => (Some(15_000), Some(10), Some(1)),
+            2 => (Some(10_000), Some(10), Some(1)),
+            3 => (None, None, None),
+            4 => (Some(1250), Some(6), None),
+            5 => (Some(300), Some(2), None),
+            _ => unreachable!(),
+        };
+
+        Self {
+            timeout: timeout.map(Duration::from_millis),
+            retries,
+            max_parallelism,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// TCP flags sent by a scan with custom flags, named as nmap's
+/// `--scanflags` takes them (e.g. `SYNFIN`) or given as a number (e.g.
+/// `0x03`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanFlags(pub u8);
+
+impl ScanFlags {
+    const NAMES: [(&'static str, u8); 8] = [
+        ("CWR", TcpFlags::CWR),
+        ("ECE", TcpFlags::ECE),
+        ("URG", TcpFlags::URG),
+        ("ACK", TcpFlags::ACK),
+        ("PSH", TcpFlags::PSH),
+        ("RST", TcpFlags::RST),
+        ("SYN", TcpFlags::SYN),
+        ("FIN", TcpFlags::FIN),
+    ];
+}
+
+impl std::str::FromStr for ScanFlags {
+    type Err = ScanError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let invalid = || ScanError::InvalidFlags(String::from(raw));
+        if let Some(hex) = raw.strip_prefix("0x").or_else(|| raw.strip_prefix("0X")) {
+            return u8::from_str_radix(hex, 16).map(Self).map_err(|_| invalid());
+        }
+        if let Ok(flags) = u8::from_str_radix(raw, 16) {
+            return Ok(Self(flags));
+        }
+
+        let mut flags = 0;
+        let mut rest = raw.to_ascii_uppercase();
+        while !rest.is_empty() {
+            let (_, flag) = Self::NAMES
+                .iter()
+                .find(|(name, _)| rest.starts_with(name))
+                .ok_or_else(invalid)?;
+            flags |= flag;
+            rest.drain(..3);
+        }
+
+        Ok(Self(flags))
+    }
+}
+
+impl Display for ScanFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "NONE");
+        }
+
+        Self::NAMES
+            .iter()
+            .filter(|(_, flag)| self.0 & flag != 0)
+            .try_for_each(|(name, _)| write!(f, "{}", name))
+    }
+}
+
+/// How the replies to segments with custom flags are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagsStyle {
+    /// As the SYN scan does: a SYN/ACK means open and a reset closed.
+    Syn,
+    /// As the FIN scan does: a reset means closed and no reply open or
+    /// filtered.
+    Fin,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum ScanType {
     Tcp,
     Syn,
+    StatelessSyn,
     Udp,
+    Fin,
+    Null,
+    Xmas,
+    Ack,
+    Window,
+    Maimon,
+    /// Scan sending custom flags, whose replies are read in `style`.
+    Flags {
+        flags: ScanFlags,
+        style: FlagsStyle,
+    },
+    /// Technique registered with [`Scanner::register`].
+    Custom {
+        name: &'static str,
+        protocol: Protocol,
+    },
+}
+
+impl ScanType {
+    /// Checks if the technique crafts its own packets, which requires sudo
+    /// and an interface address of the target's IP version.
+    pub fn is_raw(&self) -> bool {
+        !matches!(self, ScanType::Tcp | ScanType::Custom { .. })
+    }
+
+    /// Short name of the technique, as it's serialized.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScanType::Tcp => "tcp",
+            ScanType::Syn => "syn",
+            ScanType::StatelessSyn => "stateless-syn",
+            ScanType::Udp => "udp",
+            ScanType::Fin => "fin",
+            ScanType::Null => "null",
+            ScanType::Xmas => "xmas",
+            ScanType::Ack => "ack",
+            ScanType::Window => "window",
+            ScanType::Maimon => "maimon",
+            ScanType::Flags { .. } => "scanflags",
+            ScanType::Custom { name, .. } => name,
+        }
+    }
+
+    /// Transport protocol of the probed ports.
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            ScanType::Udp => Protocol::Udp,
+            ScanType::Custom { protocol, .. } => *protocol,
+            _ => Protocol::Tcp,
+        }
+    }
+}
+
+impl Serialize for ScanType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
 }
 
 impl Display for ScanType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let ScanType::Flags { flags, .. } = self {
+            return write!(f, "TCP {}", flags);
+        }
+
         write!(
             f,
             "{}",
             match self {
                 ScanType::Tcp => "TCP",
                 ScanType::Syn => "TCP SYN",
+                ScanType::StatelessSyn => "TCP SYN (stateless)",
                 ScanType::Udp => "UDP",
+                ScanType::Fin => "TCP FIN",
+                ScanType::Null => "TCP NULL",
+                ScanType::Xmas => "TCP Xmas",
+                ScanType::Ack => "TCP ACK",
+                ScanType::Window => "TCP Window",
+                ScanType::Maimon => "TCP Maimon",
+                ScanType::Flags { .. } => unreachable!(),
+                ScanType::Custom { name, .. } => name,
             }
         )
     }
@@ -67,103 +443,742 @@ impl Display for ScanType {
 
 #[derive(Debug)]
 pub struct Technique {
-    executor: &'static dyn Executor,
+    executor: Box<dyn Executor>,
     pub kind: ScanType,
 }
 
 impl Technique {
-    fn new(executor: &'static dyn Executor, kind: ScanType) -> Self {
-        Self { executor, kind }
+    fn new(executor: impl Executor + 'static, kind: ScanType) -> Self {
+        Self {
+            executor: Box::new(executor),
+            kind,
+        }
     }
 
     pub fn from(raw: &str) -> Technique {
         match raw {
-            "tcp" => Self::new(&TcpScan, ScanType::Tcp),
-            "syn" => Self::new(&SynScan, ScanType::Syn),
+            "tcp" => Self::new(TcpScan::default(), ScanType::Tcp),
+            "syn" => Self::new(SynScan::syn(), ScanType::Syn),
+            "udp" => Self::new(UdpScan, ScanType::Udp),
+            "fin" => Self::new(FinScan::fin(), ScanType::Fin),
+            "null" => Self::new(FinScan::null(), ScanType::Null),
+            "xmas" => Self::new(FinScan::xmas(), ScanType::Xmas),
+            "ack" => Self::new(AckScan::ack(), ScanType::Ack),
+            "window" => Self::new(AckScan::window(), ScanType::Window),
+            "maimon" => Self::new(FinScan::maimon(), ScanType::Maimon),
             _ => unreachable!(),
         }
     }
+
+    /// Scan sending `flags` through the same path as the SYN scan, whose
+    /// replies are read in `style`.
+    pub fn scan_flags(flags: ScanFlags, style: FlagsStyle) -> Technique {
+        let kind = ScanType::Flags { flags, style };
+        match style {
+            FlagsStyle::Syn => Self::new(SynScan::with_flags(flags.0), kind),
+            FlagsStyle::Fin => Self::new(FinScan::with_flags(flags.0), kind),
+        }
+    }
+
+    /// Stateless SYN scan sending `rate` packets per second.
+    pub fn stateless_syn(rate: u32) -> Technique {
+        Self::new(StatelessSynScan::new(rate), ScanType::StatelessSyn)
+    }
+
+    /// Technique probing `protocol` ports with `executor`, reported under
+    /// `name`.
+    pub fn custom(
+        name: &'static str,
+        protocol: Protocol,
+        executor: impl Executor + 'static,
+    ) -> Technique {
+        Self::new(executor, ScanType::Custom { name, protocol })
+    }
 }
 
 pub enum PortsToScan {
+    /// Ports in [`COMMON_PORTS`].
     All,
+    /// Every port from 1 to 65535.
+    Full,
+    /// First N most frequent ports, up to the 100 in [`TOP_PORTS`].
+    Top(usize),
     Selected(Vec<u16>),
+    Ranges(Vec<RangeInclusive<u16>>),
+    /// Ports of the first set that aren't in any of the given ranges.
+    Excluding(Box<PortsToScan>, Vec<RangeInclusive<u16>>),
 }
 
-#[derive(Debug, Clone, Copy)]
+impl PortsToScan {
+    pub fn excluding(self, ranges: Vec<RangeInclusive<u16>>) -> Self {
+        match self {
+            _ if ranges.is_empty() => self,
+            PortsToScan::Excluding(ports, mut excluded) => {
+                excluded.extend(ranges);
+                PortsToScan::Excluding(ports, excluded)
+            }
+            ports => PortsToScan::Excluding(Box::new(ports), ranges),
+        }
+    }
+
+    /// Iterates over the ports in no particular order.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = u16> + '_ {
+        // Exclusions might be nested if the enum was built by hand.
+        let mut ports = self;
+        let mut excluded = vec![];
+        while let PortsToScan::Excluding(inner, ranges) = ports {
+            excluded.extend(ranges);
+            ports = inner;
+        }
+
+        let iter = match ports {
+            PortsToScan::All => Either::Left(COMMON_PORTS.par_iter().copied()),
+            // Capped at the size of the ranked list.
+            PortsToScan::Top(n) => {
+                Either::Left(TOP_PORTS[..*n.min(&TOP_PORTS.len())].par_iter().copied())
+            }
+            PortsToScan::Selected(ports) => Either::Left(ports.par_iter().copied()),
+            PortsToScan::Full => Either::Right(Either::Left((1..=u16::MAX).into_par_iter())),
+            PortsToScan::Ranges(ranges) => Either::Right(Either::Right(
+                ranges.par_iter().flat_map(|r| r.clone().into_par_iter()),
+            )),
+            PortsToScan::Excluding(..) => unreachable!(),
+        };
+
+        iter.filter(move |p| !excluded.iter().any(|r| r.contains(p)))
+    }
+}
+
+/// Checks that the default interface has an address of the IP version of
+/// every target, so that raw techniques can craft packets to them.
+pub fn check_sources(ips: &[IpAddr]) -> Result<(), ScanError> {
+    let interface = interface::default()?;
+
+    match ips.iter().find(|ip| !interface.can_reach(ip)) {
+        Some(&ip) => Err(ScanError::MissingSourceAddr(ip)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct PortResult {
     pub port: u16,
+    pub protocol: Protocol,
     pub state: PortState,
+    pub reason: Reason,
+    #[serde(rename = "method")]
     pub kind: ScanType,
+    /// Time from sending the probe until its state was known.
+    #[serde(rename = "elapsed_ms", serialize_with = "ser::millis")]
+    pub elapsed: Duration,
+    /// Why the port couldn't be scanned, if its state is unknown for that.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "ser::opt_display"
+    )]
+    pub error: Option<ScanError>,
+    /// What listens on the port, if it's open and service detection is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<Service>,
+    /// What the port sent once connected, with unprintable bytes escaped,
+    /// if banners are grabbed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// TLS served on the port, if it's open and TLS inspection is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
 }
 
 impl PortResult {
-    fn new(port: u16, state: PortState, kind: ScanType) -> Self {
-        Self { port, state, kind }
+    fn new(port: u16, verdict: Verdict, kind: ScanType, elapsed: Duration) -> Self {
+        let (state, reason, error) = match verdict {
+            Ok((state, reason)) => (state, reason, None),
+            Err(e) => (PortState::Unknown, Reason::Error, Some(e)),
+        };
+
+        Self {
+            port,
+            protocol: kind.protocol(),
+            state,
+            reason,
+            kind,
+            elapsed,
+            error,
+            service: None,
+            banner: None,
+            tls: None,
+        }
+    }
+
+    /// Keeps the banner read from the port, if any, as printable text.
+    fn with_banner(mut self, banner: Option<Vec<u8>>) -> Self {
+        self.banner = banner.map(|b| banner::sanitise(&b));
+        self
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+pub struct HostResult {
+    pub ip: IpAddr,
+    pub ports: Vec<PortResult>,
+}
+
+impl HostResult {
+    #[inline]
+    fn new(ip: IpAddr, ports: Vec<PortResult>) -> Self {
+        Self { ip, ports }
+    }
+
+    /// Counts the ports found closed by every technique.
+    pub fn closed(&self) -> usize {
+        self.ports
+            .iter()
+            .filter(|pr| pr.state == PortState::Closed)
+            .count()
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct ScanResult {
+    #[serde(serialize_with = "ser::rfc3339")]
+    pub started: SystemTime,
+    #[serde(rename = "elapsed_ms", serialize_with = "ser::millis")]
     pub elapsed: Duration,
-    pub ports: Vec<PortResult>,
+    pub hosts: Vec<HostResult>,
 }
 
 impl ScanResult {
     #[inline]
-    fn new(elapsed: Duration, ports: Vec<PortResult>) -> Self {
-        Self { elapsed, ports }
+    fn new(started: SystemTime, elapsed: Duration, hosts: Vec<HostResult>) -> Self {
+        Self {
+            started,
+            elapsed,
+            hosts,
+        }
+    }
+
+    /// Time at which the scan finished.
+    pub fn finished(&self) -> SystemTime {
+        self.started + self.elapsed
     }
 }
 
+/// How far a scan got, handed to its progress callback every time the
+/// state of a port is final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Ports whose state is final, across every target.
+    pub done: usize,
+    pub total: usize,
+}
+
+type ProgressCallback = Box<dyn Fn(Progress) + Send + Sync>;
+
+/// Report written once the scan finishes.
+struct Output {
+    format: Format,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
 pub struct Scanner {
-    ip: Ipv4Addr,
+    /// Targets as given, before being resolved.
+    targets: Vec<String>,
+    ips: Vec<IpAddr>,
     ports: PortsToScan,
     techniques: Vec<Technique>,
+    options: ScanOptions,
+    outputs: Vec<Output>,
+    progress: Option<ProgressCallback>,
+    /// Whether the services listening on open TCP ports are identified.
+    detect_services: bool,
+    /// Whether the TLS served on open TCP ports is inspected.
+    inspect_tls: bool,
 }
 
 impl Scanner {
-    pub fn new(ip: Ipv4Addr, ports: PortsToScan, techniques: Vec<Technique>) -> Self {
+    pub fn new(
+        ips: Vec<IpAddr>,
+        ports: PortsToScan,
+        techniques: Vec<Technique>,
+        options: ScanOptions,
+    ) -> Self {
         Self {
-            ip,
+            targets: ips.iter().map(IpAddr::to_string).collect(),
+            ips,
             ports,
             techniques,
+            options,
+            outputs: vec![],
+            progress: None,
+            detect_services: false,
+            inspect_tls: false,
+        }
+    }
+
+    /// Starts configuring a scanner, checked as a whole once it's built.
+    pub fn builder() -> ScannerBuilder {
+        ScannerBuilder::default()
+    }
+
+    /// Addresses of the targets to scan.
+    pub fn ips(&self) -> &[IpAddr] {
+        &self.ips
+    }
+
+    /// Describes the scan that produced `result`, started by the command
+    /// line `args` if any.
+    pub fn report<'a>(&'a self, args: &'a [String], result: &'a ScanResult) -> Report<'a> {
+        Report {
+            args,
+            targets: &self.targets,
+            ips: &self.ips,
+            techniques: self.techniques.iter().map(|t| t.kind).collect(),
+            options: &self.options,
+            result,
+        }
+    }
+
+    /// Appends a technique probing `protocol` ports with `executor`, run
+    /// after the ones already added. See [`Technique::custom`].
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        protocol: Protocol,
+        executor: impl Executor + 'static,
+    ) -> &mut Self {
+        self.techniques
+            .push(Technique::custom(name, protocol, executor));
+        self
+    }
+
+    /// Scans the ports with each technique in turn, where the next one only
+    /// probes the ports that the previous ones found closed. Ports that are
+    /// closed to every technique are reported as the last one found them.
+    fn scan_host(&self, ip: IpAddr, sink: &(dyn Fn(IpAddr, &PortResult) + Sync)) -> HostResult {
+        let mut chain = Chain::new(self.ports.par_iter().collect());
+
+        for technique in &self.techniques {
+            let scan = |port| {
+                technique
+                    .executor
+                    .scan(&SocketAddr::new(ip, port), &self.options)
+            };
+            // Closed ports are only final once every technique is done.
+            let settle = |i, port, verdict, sent: Instant| {
+                let result = PortResult::new(port, verdict, technique.kind, sent.elapsed())
+                    .with_banner(technique.executor.banner(&SocketAddr::new(ip, port)));
+                if result.state != PortState::Closed {
+                    sink(ip, &result);
+                }
+
+                (i, result)
+            };
+
+            let results = match self.options.max_parallelism {
+                // Each worker waits for its probe before sending another.
+                Some(_) => chain
+                    .remaining
+                    .par_iter()
+                    .map(|&(i, port)| {
+                        let sent = Instant::now();
+                        settle(i, port, scan(port).wait(), sent)
+                    })
+                    .collect::<Vec<_>>(),
+                // Every probe is sent before waiting for any reply.
+                None => chain
+                    .remaining
+                    .par_iter()
+                    .map(|&(i, port)| (i, port, Instant::now(), scan(port)))
+                    .collect::<Vec<_>>()
+                    .into_par_iter()
+                    .map(|(i, port, sent, probe)| settle(i, port, probe.wait(), sent))
+                    .collect::<Vec<_>>(),
+            };
+
+            chain.settle(results);
+        }
+
+        chain.finish(ip, sink)
+    }
+
+    /// Scans every target and returns the results once all of them are
+    /// done. Errors that only affect some ports are recorded in their
+    /// results instead.
+    pub fn start(&self) -> Result<ScanResult, ScanError> {
+        self.run(|_, _| ())
+    }
+
+    /// Scans every target like [`Scanner::start`], handing each port to
+    /// `sink` from the scanning threads as soon as its state is final. The
+    /// outputs are written once every target is done, and the first one
+    /// that fails is returned.
+    pub fn run<F>(&self, sink: F) -> Result<ScanResult, ScanError>
+    where
+        F: Fn(IpAddr, &PortResult) + Sync,
+    {
+        let started = SystemTime::now();
+        let now = Instant::now();
+
+        self.prepare()?;
+
+        let tracker = Tracker::new(self, sink);
+        let sink = |ip, pr: &PortResult| tracker.settled(ip, pr);
+
+        let scan = || {
+            let mut hosts = self
+                .ips
+                .par_iter()
+                .map(|&ip| self.scan_host(ip, &sink))
+                .collect::<Vec<_>>();
+            inspect(
+                &mut hosts,
+                self.detect_services,
+                self.inspect_tls,
+                self.options.timeout,
+            );
+
+            hosts
+        };
+        let hosts = match self.options.max_parallelism {
+            Some(threads) => ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(scan),
+            None => scan(),
+        };
+
+        self.finish(ScanResult::new(started, now.elapsed(), hosts))
+    }
+
+    /// Gets every technique ready before the first port is scanned.
+    fn prepare(&self) -> Result<(), ScanError> {
+        rate::LIMITER.configure(self.options.max_rate);
+        if self.techniques.iter().any(|t| t.kind.is_raw()) {
+            check_sources(&self.ips)?;
+            engine::start(&self.options)?;
+        }
+
+        for technique in &self.techniques {
+            technique
+                .executor
+                .prepare(&self.ips, &self.ports, &self.options)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `result` to the outputs.
+    fn finish(&self, result: ScanResult) -> Result<ScanResult, ScanError> {
+        let report = self.report(&[], &result);
+        for output in &self.outputs {
+            let mut out = output.out.lock().unwrap();
+            output
+                .format
+                .write(&report, &mut *out)
+                .and_then(|_| out.flush())
+                .map_err(|e| {
+                    ScanError::OutputFailed(format!("{} output", output.format.name()), e.into())
+                })?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Looks into the open ports of `hosts` once every technique is done,
+/// identifying their `services` and inspecting their `tls` if asked.
+fn inspect(hosts: &mut [HostResult], services: bool, tls: bool, timeout: Option<Duration>) {
+    if services {
+        service::detect(hosts, timeout);
+    }
+    if tls {
+        tls::inspect(hosts, timeout);
+    }
+}
+
+/// Ports of a host left for the next technique, along with the results of
+/// the ones scanned so far, which keep the order of the requested ports.
+struct Chain {
+    remaining: Vec<(usize, u16)>,
+    found: Vec<(usize, PortResult)>,
+    closed: Vec<(usize, PortResult)>,
+}
+
+impl Chain {
+    fn new(ports: Vec<u16>) -> Self {
+        Self {
+            remaining: ports.into_iter().enumerate().collect(),
+            found: vec![],
+            closed: vec![],
         }
     }
 
-    fn scan_port(&self, executor: &'static dyn Executor, port: u16) -> Option<PortState> {
-        let addr = SocketAddrV4::new(self.ip, port);
-        let state = executor.scan(&addr);
-        if state == PortState::_Closed {
-            return None;
+    /// Keeps the results of a technique, where the closed ports are left
+    /// for the next one.
+    fn settle(&mut self, results: Vec<(usize, PortResult)>) {
+        self.remaining.clear();
+        self.closed.clear();
+        for (i, result) in results {
+            match result.state {
+                PortState::Closed => {
+                    self.remaining.push((i, result.port));
+                    self.closed.push((i, result));
+                }
+                _ => self.found.push((i, result)),
+            }
         }
-        Some(state)
     }
 
-    fn scan_all(&self) -> rayon::slice::Iter<'_, u16> {
-        COMMON_PORTS.into_par_iter()
+    /// Hands the ports that every technique found closed to `sink`.
+    fn finish(mut self, ip: IpAddr, sink: &(dyn Fn(IpAddr, &PortResult) + Sync)) -> HostResult {
+        self.closed.iter().for_each(|(_, pr)| sink(ip, pr));
+        self.found.append(&mut self.closed);
+
+        // Ports keep the order in which they were requested.
+        self.found.sort_unstable_by_key(|&(i, _)| i);
+
+        HostResult::new(ip, self.found.into_iter().map(|(_, pr)| pr).collect())
     }
+}
+
+/// Hands each final port state to the sink of a scan and reports the
+/// progress made to its callback.
+struct Tracker<'a, F> {
+    progress: Option<&'a ProgressCallback>,
+    sink: F,
+    done: AtomicUsize,
+    total: usize,
+}
+
+impl<'a, F: Fn(IpAddr, &PortResult) + Sync> Tracker<'a, F> {
+    fn new(scanner: &'a Scanner, sink: F) -> Self {
+        let total = match scanner.progress {
+            Some(_) => scanner.ips.len() * scanner.ports.par_iter().count(),
+            None => 0,
+        };
 
-    fn scan_selected<'a>(&self, ports: &'a [u16]) -> rayon::slice::Iter<'a, u16> {
-        ports.into_par_iter()
+        Self {
+            progress: scanner.progress.as_ref(),
+            sink,
+            done: AtomicUsize::new(0),
+            total,
+        }
     }
 
-    pub fn start(&self) -> ScanResult {
-        let iter = match self.ports {
-            PortsToScan::All => self.scan_all(),
-            PortsToScan::Selected(ref ports) => self.scan_selected(ports),
+    fn settled(&self, ip: IpAddr, pr: &PortResult) {
+        (self.sink)(ip, pr);
+        if let Some(progress) = self.progress {
+            let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(Progress {
+                done,
+                total: self.total,
+            });
         }
-        .filter_map(|&port| {
-            self.techniques.iter().find_map(|t| {
-                self.scan_port(t.executor, port)
-                    .map(|state| PortResult::new(port, state, t.kind))
-            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_flags_are_named_or_hex() {
+        let flags = |raw: &str| raw.parse::<ScanFlags>().map(|flags| flags.0);
+
+        assert_eq!(flags("SYNFIN").unwrap(), TcpFlags::SYN | TcpFlags::FIN);
+        assert_eq!(flags("urgackpshrstsynfin").unwrap(), 0x3f);
+        assert_eq!(flags("0x12").unwrap(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(flags("29").unwrap(), 0x29);
+        assert!(flags("SYNFOO").is_err());
+        assert!(flags("0x100").is_err());
+
+        assert_eq!(ScanFlags(0x29).to_string(), "URGPSHFIN");
+        assert_eq!(ScanFlags(0).to_string(), "NONE");
+    }
+
+    fn collect(ports: &PortsToScan) -> Vec<u16> {
+        let mut ports = ports.par_iter().collect::<Vec<_>>();
+        ports.sort_unstable();
+        ports
+    }
+
+    #[test]
+    fn run_streams_final_states() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // Bound and dropped, so nothing listens on it anymore.
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let scanner = Scanner::new(
+            vec![IpAddr::from([127, 0, 0, 1])],
+            PortsToScan::Selected(vec![closed, open]),
+            vec![Technique::from("tcp")],
+            ScanOptions::default(),
+        );
+        let streamed = std::sync::Mutex::new(vec![]);
+        let result = scanner
+            .run(|_, pr| streamed.lock().unwrap().push((pr.port, pr.state)))
+            .unwrap();
+
+        let mut streamed = streamed.into_inner().unwrap();
+        streamed.sort_unstable_by_key(|&(port, _)| port != open);
+        assert_eq!(
+            streamed,
+            [(open, PortState::Open), (closed, PortState::Closed)]
+        );
+
+        let ports = &result.hosts[0].ports;
+        assert_eq!(ports[0].port, closed);
+        assert_eq!(ports[0].reason, Reason::ConnRefused);
+        assert_eq!(ports[1].state, PortState::Open);
+    }
+
+    #[test]
+    fn banners_are_grabbed_from_open_ports() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"220 ready\r\n").unwrap();
         });
 
-        let now = Instant::now();
-        let ports = iter.collect();
-        let elapsed = now.elapsed();
+        let scanner = Scanner::new(
+            vec![IpAddr::from([127, 0, 0, 1])],
+            PortsToScan::Selected(vec![port]),
+            vec![Technique::from("tcp")],
+            ScanOptions {
+                banner: Some(BannerGrab::default()),
+                ..Default::default()
+            },
+        );
+        let result = scanner.start().unwrap();
+
+        assert_eq!(
+            result.hosts[0].ports[0].banner.as_deref(),
+            Some("220 ready\\r\\n")
+        );
+    }
+
+    #[derive(Debug)]
+    struct EvenOpen;
+
+    impl Executor for EvenOpen {
+        fn scan(&self, addr: &SocketAddr, _options: &ScanOptions) -> Probe {
+            let state = match addr.port() % 2 {
+                0 => PortState::Open,
+                _ => PortState::Closed,
+            };
+
+            Probe::Waiting(Box::new(move || Ok((state, Reason::UdpResponse))))
+        }
+    }
+
+    #[test]
+    fn registered_techniques_are_chained() {
+        let mut scanner = Scanner::new(
+            vec![IpAddr::from([127, 0, 0, 1])],
+            PortsToScan::Selected(vec![1, 2]),
+            vec![],
+            ScanOptions::default(),
+        );
+        scanner.register("even", Protocol::Udp, EvenOpen).register(
+            "even-again",
+            Protocol::Udp,
+            EvenOpen,
+        );
+        let result = scanner.start().unwrap();
+
+        let ports = &result.hosts[0].ports;
+        assert_eq!((ports[0].port, ports[0].state), (1, PortState::Closed));
+        assert_eq!(ports[0].kind.name(), "even-again");
+        assert_eq!((ports[1].port, ports[1].state), (2, PortState::Open));
+        assert_eq!(ports[1].kind.name(), "even");
+        assert_eq!(ports[1].protocol, Protocol::Udp);
+    }
+
+    #[test]
+    fn errors_are_recorded_per_port() {
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let pr = PortResult::new(
+            80,
+            Err(ScanError::MissingSourceAddr(ip)),
+            ScanType::Syn,
+            Duration::ZERO,
+        );
+
+        assert_eq!((pr.state, pr.reason), (PortState::Unknown, Reason::Error));
+        assert!(matches!(pr.error, Some(ScanError::MissingSourceAddr(e)) if e == ip));
+    }
+
+    #[test]
+    fn normal_timing_keeps_defaults() {
+        let options = ScanOptions::template(3);
+
+        assert!(options.timeout.is_none() && options.retries.is_none());
+        assert!(options.max_parallelism.is_none());
+    }
+
+    #[test]
+    fn timing_templates_get_faster() {
+        let timeouts = (0..=5)
+            .filter_map(|level| ScanOptions::template(level).timeout)
+            .collect::<Vec<_>>();
+
+        assert_eq!(timeouts.len(), 5);
+        assert!(timeouts.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn reasons_count_retries() {
+        assert_eq!(Reason::NoResponse(0).to_string(), "no-response");
+        assert_eq!(Reason::NoResponse(3).to_string(), "no-response (3 retries)");
+        assert_eq!(Reason::IcmpPortUnreach.to_string(), "icmp-port-unreach");
+    }
+
+    #[test]
+    fn ranges_and_full() {
+        assert_eq!(
+            collect(&PortsToScan::Ranges(vec![1..=3, 10..=10])),
+            [1, 2, 3, 10]
+        );
+        assert_eq!(collect(&PortsToScan::Full).len(), u16::MAX as usize);
+    }
+
+    #[test]
+    fn top_ports_are_capped() {
+        assert_eq!(collect(&PortsToScan::Top(5)), [21, 22, 23, 80, 443]);
+        assert_eq!(collect(&PortsToScan::Top(5000)).len(), TOP_PORTS.len());
+    }
+
+    #[test]
+    fn excluding_flattens() {
+        let ports = PortsToScan::Ranges(vec![1..=10])
+            .excluding(vec![2..=3])
+            .excluding(vec![5..=5]);
+
+        assert!(matches!(
+            ports,
+            PortsToScan::Excluding(ref inner, ref excluded)
+                if matches!(**inner, PortsToScan::Ranges(_)) && excluded.len() == 2
+        ));
+        assert_eq!(collect(&ports), [1, 4, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn nested_excluding_is_iterated() {
+        let ports = PortsToScan::Excluding(
+            Box::new(PortsToScan::Excluding(
+                Box::new(PortsToScan::Selected(vec![1, 2, 3])),
+                vec![1..=1],
+            )),
+            vec![3..=3],
+        );
 
-        ScanResult::new(elapsed, ports)
+        assert_eq!(collect(&ports), [2]);
     }
 }
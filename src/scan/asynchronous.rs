@@ -0,0 +1,202 @@
+//! Scans driven by tokio, where connect probes are non-blocking sockets
+//! instead of threads blocked on them, so that thousands of them can be in
+//! flight at once.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rayon::prelude::ParallelIterator;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::error::ScanError;
+
+use super::{
+    inspect,
+    method::{connect, TCP_TIMEOUT},
+    Chain, PortResult, PortState, ScanResult, ScanType, Scanner, Tracker, Verdict,
+};
+
+// Probes in flight at once, unless the scan limits its parallelism.
+const DEFAULT_CONCURRENCY: usize = 1000;
+
+/// Host and port of a probe, as indexes and the port itself, along with
+/// its verdict, how long it took and the banner read by connect probes.
+type Finished = (usize, usize, u16, Verdict, Duration, Option<Vec<u8>>);
+
+impl Scanner {
+    /// Scans every target like [`Scanner::start`] on the tokio runtime it's
+    /// awaited in, which needs its time and IO drivers enabled.
+    ///
+    /// The TCP technique connects without blocking any thread, up to
+    /// [`ScanOptions::max_parallelism`](super::ScanOptions::max_parallelism)
+    /// ports at once. Other techniques send their probes from the calling
+    /// task and wait for the replies on tokio's blocking threads.
+    pub async fn start_async(&self) -> Result<ScanResult, ScanError> {
+        self.run_async(|_, _| ()).await
+    }
+
+    /// Scans every target like [`Scanner::start_async`], handing each port
+    /// to `sink` as soon as its state is final.
+    pub async fn run_async<F>(&self, sink: F) -> Result<ScanResult, ScanError>
+    where
+        F: Fn(IpAddr, &PortResult) + Sync,
+    {
+        let started = SystemTime::now();
+        let now = Instant::now();
+
+        self.prepare()?;
+
+        let tracker = Tracker::new(self, sink);
+        let sink = |ip, pr: &PortResult| tracker.settled(ip, pr);
+
+        let limit = Arc::new(Semaphore::new(
+            self.options.max_parallelism.unwrap_or(DEFAULT_CONCURRENCY),
+        ));
+        let timeout = self.options.timeout.unwrap_or(TCP_TIMEOUT);
+        let grab = self.options.banner.clone().map(Arc::new);
+
+        let ports = self.ports.par_iter().collect::<Vec<_>>();
+        let mut chains = self
+            .ips
+            .iter()
+            .map(|_| Chain::new(ports.clone()))
+            .collect::<Vec<_>>();
+
+        // Every host goes through a technique before the next one starts.
+        for technique in &self.techniques {
+            let kind = technique.kind;
+            let mut results = vec![vec![]; chains.len()];
+            let mut probes = JoinSet::new();
+
+            // Closed ports are only final once every technique is done.
+            let mut settle = |(host, i, port, verdict, elapsed, banner): Finished| {
+                let ip = self.ips[host];
+                let banner =
+                    banner.or_else(|| technique.executor.banner(&SocketAddr::new(ip, port)));
+                let result = PortResult::new(port, verdict, kind, elapsed).with_banner(banner);
+                if result.state != PortState::Closed {
+                    sink(ip, &result);
+                }
+
+                results[host].push((i, result));
+            };
+
+            for (host, chain) in chains.iter().enumerate() {
+                let ip = self.ips[host];
+
+                for &(i, port) in &chain.remaining {
+                    let permit = limit.clone().acquire_owned().await.unwrap();
+                    let addr = SocketAddr::new(ip, port);
+                    let sent = Instant::now();
+
+                    match kind {
+                        ScanType::Tcp => probes.spawn({
+                            let grab = grab.clone();
+                            async move {
+                                let (verdict, banner) =
+                                    connect(addr, timeout, grab.as_deref()).await;
+                                drop(permit);
+
+                                (host, i, port, Ok(verdict), sent.elapsed(), banner)
+                            }
+                        }),
+                        _ => {
+                            let probe = technique.executor.scan(&addr, &self.options);
+                            probes.spawn_blocking(move || {
+                                let verdict = probe.wait();
+                                drop(permit);
+
+                                (host, i, port, verdict, sent.elapsed(), None)
+                            })
+                        }
+                    };
+
+                    // Ports are streamed while the others are still sent.
+                    while let Some(done) = probes.try_join_next() {
+                        settle(done.unwrap());
+                    }
+                }
+            }
+
+            while let Some(done) = probes.join_next().await {
+                settle(done.unwrap());
+            }
+
+            chains
+                .iter_mut()
+                .zip(results)
+                .for_each(|(chain, results)| chain.settle(results));
+        }
+
+        let mut hosts = chains
+            .into_iter()
+            .zip(&self.ips)
+            .map(|(chain, &ip)| chain.finish(ip, &sink))
+            .collect::<Vec<_>>();
+
+        if self.detect_services || self.inspect_tls {
+            let (services, tls, timeout) =
+                (self.detect_services, self.inspect_tls, self.options.timeout);
+            hosts = tokio::task::spawn_blocking(move || {
+                inspect(&mut hosts, services, tls, timeout);
+                hosts
+            })
+            .await
+            .unwrap();
+        }
+
+        self.finish(ScanResult::new(started, now.elapsed(), hosts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crate::scan::{PortsToScan, Reason, ScanOptions, Technique};
+
+    use super::*;
+
+    #[test]
+    fn connects_without_blocking() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let scanner = Scanner::new(
+            vec![IpAddr::from([127, 0, 0, 1])],
+            PortsToScan::Selected(vec![open, closed]),
+            vec![Technique::from("tcp")],
+            ScanOptions {
+                max_parallelism: Some(1),
+                ..Default::default()
+            },
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let scan = scanner.start_async();
+        // Scans can be spawned on multithreaded runtimes.
+        fn assert_send(_: &impl Send) {}
+        assert_send(&scan);
+        let result = runtime.block_on(scan).unwrap();
+
+        let ports = &result.hosts[0].ports;
+        assert_eq!(
+            (ports[0].state, ports[0].reason),
+            (PortState::Open, Reason::SynAck)
+        );
+        assert_eq!(
+            (ports[1].state, ports[1].reason),
+            (PortState::Closed, Reason::ConnRefused)
+        );
+    }
+}
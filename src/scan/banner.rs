@@ -0,0 +1,166 @@
+//! Banner grabbing: what a service sends once connected, which often names
+//! its software before any request.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::ser;
+
+/// How the TCP technique reads the banner of open ports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BannerGrab {
+    /// How long to read for once connected.
+    #[serde(rename = "wait_ms", serialize_with = "ser::millis")]
+    pub wait: Duration,
+    /// Most bytes kept of the banner.
+    pub max_len: usize,
+    /// Sent once connected, for services that wait for the client to speak
+    /// first (e.g. HTTP).
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "opt_sanitised"
+    )]
+    pub probe: Option<Vec<u8>>,
+}
+
+impl Default for BannerGrab {
+    fn default() -> Self {
+        Self {
+            wait: Duration::from_millis(1000),
+            max_len: 512,
+            probe: None,
+        }
+    }
+}
+
+/// Sends the probe of `grab` over `stream` and reads the response until
+/// the wait is over, the connection is closed or enough bytes arrived.
+/// Failures end the banner, which is only ever partial then.
+pub fn read(mut stream: &TcpStream, grab: &BannerGrab) -> Vec<u8> {
+    let mut banner = vec![];
+    if let Some(probe) = &grab.probe {
+        if stream.write_all(probe).is_err() {
+            return banner;
+        }
+    }
+
+    let start = Instant::now();
+    let mut buf = [0; 1024];
+    while banner.len() < grab.max_len {
+        let Some(left) = grab.wait.checked_sub(start.elapsed()) else {
+            break;
+        };
+        if stream
+            .set_read_timeout(Some(left.max(Duration::from_millis(1))))
+            .is_err()
+        {
+            break;
+        }
+
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => banner.extend(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(_) => break,
+        }
+    }
+
+    banner.truncate(grab.max_len);
+    banner
+}
+
+/// Reads the banner like [`read`] without blocking the thread.
+#[cfg(feature = "async")]
+pub async fn read_async(stream: &mut tokio::net::TcpStream, grab: &BannerGrab) -> Vec<u8> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut banner = vec![];
+    if let Some(probe) = &grab.probe {
+        if stream.write_all(probe).await.is_err() {
+            return banner;
+        }
+    }
+
+    let deadline = tokio::time::Instant::now() + grab.wait;
+    let mut buf = [0; 1024];
+    while banner.len() < grab.max_len {
+        match tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => banner.extend(&buf[..n]),
+            _ => break,
+        }
+    }
+
+    banner.truncate(grab.max_len);
+    banner
+}
+
+/// Turns `raw` into printable text, escaping line breaks and tabs as `\r`,
+/// `\n` and `\t` and any other unprintable byte as `\xNN`, the way nmap
+/// shows banners.
+pub fn sanitise(raw: &[u8]) -> String {
+    let mut text = String::with_capacity(raw.len());
+    for &b in raw {
+        match b {
+            b'\r' => text.push_str("\\r"),
+            b'\n' => text.push_str("\\n"),
+            b'\t' => text.push_str("\\t"),
+            b'\\' => text.push_str("\\\\"),
+            b' ' | b'!'..=b'~' => text.push(b as char),
+            b => text.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+
+    text
+}
+
+fn opt_sanitised<S: serde::Serializer>(
+    raw: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match raw {
+        Some(raw) => serializer.serialize_str(&sanitise(raw)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn unprintable_bytes_are_escaped() {
+        assert_eq!(
+            sanitise(b"220 ftp\\ ready\r\n\x00\xff"),
+            "220 ftp\\\\ ready\\r\\n\\x00\\xff"
+        );
+    }
+
+    #[test]
+    fn banner_is_read_after_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"echo ").unwrap();
+            stream.write_all(&request).unwrap();
+        });
+
+        let grab = BannerGrab {
+            max_len: 7,
+            probe: Some(b"ping".to_vec()),
+            ..Default::default()
+        };
+        let stream = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(read(&stream, &grab), b"echo pi");
+    }
+}
@@ -0,0 +1,346 @@
+use std::{io::Write, net::IpAddr, sync::Mutex, time::Duration};
+
+use crate::{error::ScanError, is_user_sudo, output::Format, resolver};
+
+use super::{
+    check_sources, interface, BannerGrab, Executor, Output, PortsToScan, Progress,
+    ProgressCallback, Protocol, ScanOptions, Scanner, Technique,
+};
+
+/// Configures a [`Scanner`], whose values are only checked together once
+/// it's built. Unset values keep the defaults of the binary: the common
+/// ports are scanned with the TCP technique and the normal timing.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use pscan::{
+///     output::Format,
+///     scan::{PortsToScan, Scanner, Technique},
+/// };
+///
+/// let scanner = Scanner::builder()
+///     .target("10.0.0.0/24")
+///     .ports(PortsToScan::Top(20))
+///     .technique(Technique::from("syn"))
+///     .timeout(Duration::from_millis(500))
+///     .max_rate(1000)
+///     .output(Format::Json, std::io::stdout())
+///     .progress(|p| eprintln!("{}/{}", p.done, p.total))
+///     .build()?;
+/// let result = scanner.start()?;
+/// # Ok::<(), pscan::error::ScanError>(())
+/// ```
+pub struct ScannerBuilder {
+    targets: Vec<String>,
+    ipv6: bool,
+    ports: PortsToScan,
+    techniques: Vec<Technique>,
+    timing: u8,
+    timeout: Option<Duration>,
+    retries: Option<usize>,
+    max_parallelism: Option<usize>,
+    max_rate: Option<u32>,
+    min_rate: Option<u32>,
+    congestion_control: bool,
+    interface: Option<String>,
+    source: Option<IpAddr>,
+    outputs: Vec<Output>,
+    progress: Option<ProgressCallback>,
+    detect_services: bool,
+    inspect_tls: bool,
+    banner: Option<BannerGrab>,
+}
+
+impl Default for ScannerBuilder {
+    fn default() -> Self {
+        Self {
+            targets: vec![],
+            ipv6: false,
+            ports: PortsToScan::All,
+            techniques: vec![],
+            timing: 3,
+            timeout: None,
+            retries: None,
+            max_parallelism: None,
+            max_rate: None,
+            min_rate: None,
+            congestion_control: false,
+            interface: None,
+            source: None,
+            outputs: vec![],
+            progress: None,
+            detect_services: false,
+            inspect_tls: false,
+            banner: None,
+        }
+    }
+}
+
+impl ScannerBuilder {
+    /// Adds a target, written in any of the forms [`resolver::expand`]
+    /// takes.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    pub fn targets<S: Into<String>>(mut self, targets: impl IntoIterator<Item = S>) -> Self {
+        self.targets.extend(targets.into_iter().map(Into::into));
+        self
+    }
+
+    /// Resolves hostnames to IPv6 addresses instead of IPv4 ones.
+    pub fn ipv6(mut self, ipv6: bool) -> Self {
+        self.ipv6 = ipv6;
+        self
+    }
+
+    pub fn ports(mut self, ports: PortsToScan) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    /// Adds a technique, which only probes the ports that the ones added
+    /// before found closed.
+    pub fn technique(mut self, technique: Technique) -> Self {
+        self.techniques.push(technique);
+        self
+    }
+
+    /// Adds a technique probing `protocol` ports with `executor`, like
+    /// [`Scanner::register`].
+    pub fn register(
+        self,
+        name: &'static str,
+        protocol: Protocol,
+        executor: impl Executor + 'static,
+    ) -> Self {
+        self.technique(Technique::custom(name, protocol, executor))
+    }
+
+    /// Timing template from 0 to 5, see [`ScanOptions::template`]. The
+    /// timing values set on their own take precedence over it.
+    pub fn timing(mut self, level: u8) -> Self {
+        self.timing = level;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    pub fn max_parallelism(mut self, max_parallelism: usize) -> Self {
+        self.max_parallelism = Some(max_parallelism);
+        self
+    }
+
+    pub fn max_rate(mut self, max_rate: u32) -> Self {
+        self.max_rate = Some(max_rate);
+        self
+    }
+
+    pub fn min_rate(mut self, min_rate: u32) -> Self {
+        self.min_rate = Some(min_rate);
+        self
+    }
+
+    pub fn congestion_control(mut self, congestion_control: bool) -> Self {
+        self.congestion_control = congestion_control;
+        self
+    }
+
+    /// Sends raw probes through the network interface called `name`
+    /// instead of the first one up.
+    pub fn interface(mut self, name: impl Into<String>) -> Self {
+        self.interface = Some(name.into());
+        self
+    }
+
+    /// Sends raw probes from `source` instead of the interface's address of
+    /// the same IP version.
+    pub fn source(mut self, source: IpAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Writes the report in `format` to `out` once the scan finishes.
+    pub fn output(mut self, format: Format, out: impl Write + Send + 'static) -> Self {
+        self.outputs.push(Output {
+            format,
+            out: Mutex::new(Box::new(out)),
+        });
+        self
+    }
+
+    /// Calls `progress` from the scanning threads every time the state of
+    /// a port is final.
+    pub fn progress(mut self, progress: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Identifies the services listening on open TCP ports once every
+    /// technique is done, as nmap's `-sV`.
+    pub fn service_detection(mut self, detect: bool) -> Self {
+        self.detect_services = detect;
+        self
+    }
+
+    /// Goes through a TLS handshake with every open TCP port once every
+    /// technique is done, describing the protocol, cipher and certificate
+    /// it serves.
+    pub fn tls_inspection(mut self, inspect: bool) -> Self {
+        self.inspect_tls = inspect;
+        self
+    }
+
+    /// Reads what open ports send once connected by the TCP technique,
+    /// see [`ScanOptions::banner`].
+    pub fn banner(mut self, grab: BannerGrab) -> Self {
+        self.banner = Some(grab);
+        self
+    }
+
+    /// Checks the configuration and resolves the targets. Raw techniques
+    /// require sudo and an interface address of the IP version of every
+    /// target.
+    pub fn build(mut self) -> Result<Scanner, ScanError> {
+        if self.targets.is_empty() {
+            return Err(ScanError::MissingTargets);
+        }
+        if self.timing > 5 {
+            return Err(ScanError::InvalidTiming(self.timing));
+        }
+        if let (Some(min), Some(max)) = (self.min_rate, self.max_rate) {
+            if min > max {
+                return Err(ScanError::InvalidRateRange(min, max));
+            }
+        }
+
+        if self.techniques.is_empty() {
+            self.techniques.push(Technique::from("tcp"));
+        }
+        let raw = self.techniques.iter().any(|t| t.kind.is_raw());
+        if raw && !is_user_sudo() {
+            return Err(ScanError::NormalUserRequired);
+        }
+
+        let ips = resolver::expand(&self.targets, self.ipv6)?;
+
+        if self.interface.is_some() || self.source.is_some() {
+            interface::select(self.interface.as_deref(), self.source)?;
+        }
+        if raw {
+            check_sources(&ips)?;
+        }
+
+        // Values given on their own take precedence over the template.
+        let template = ScanOptions::template(self.timing);
+        let options = ScanOptions {
+            timeout: self.timeout.or(template.timeout),
+            retries: self.retries.or(template.retries),
+            max_parallelism: self.max_parallelism.or(template.max_parallelism),
+            max_rate: self.max_rate,
+            min_rate: self.min_rate,
+            congestion_control: self.congestion_control,
+            banner: self.banner,
+        };
+
+        Ok(Scanner {
+            targets: self.targets,
+            ips,
+            ports: self.ports,
+            techniques: self.techniques,
+            options,
+            outputs: self.outputs,
+            progress: self.progress,
+            detect_services: self.detect_services,
+            inspect_tls: self.inspect_tls,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_combinations() {
+        let build = |builder: ScannerBuilder| builder.build().err();
+
+        assert!(matches!(
+            build(Scanner::builder()),
+            Some(ScanError::MissingTargets)
+        ));
+        assert!(matches!(
+            build(Scanner::builder().target("127.0.0.1").timing(6)),
+            Some(ScanError::InvalidTiming(6))
+        ));
+        assert!(matches!(
+            build(
+                Scanner::builder()
+                    .target("127.0.0.1")
+                    .min_rate(10)
+                    .max_rate(5)
+            ),
+            Some(ScanError::InvalidRateRange(10, 5))
+        ));
+        assert!(matches!(
+            build(Scanner::builder().target("127.0.0.1").interface("missing0")),
+            Some(ScanError::InterfaceNotFound(name)) if name == "missing0"
+        ));
+    }
+
+    #[test]
+    fn reports_progress_and_outputs() {
+        #[derive(Clone, Default)]
+        struct Shared(std::sync::Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let written = Shared::default();
+        let progress = std::sync::Arc::new(Mutex::new(vec![]));
+        let seen = progress.clone();
+
+        let scanner = Scanner::builder()
+            .targets(["127.0.0.1"])
+            .ports(PortsToScan::Selected(vec![1, 2]))
+            .timing(4)
+            .retries(1)
+            .output(Format::Csv, written.clone())
+            .progress(move |p| seen.lock().unwrap().push(p))
+            .build()
+            .unwrap();
+        assert_eq!(scanner.options.timeout, Some(Duration::from_millis(1250)));
+        assert_eq!(scanner.options.retries, Some(1));
+
+        scanner.start().unwrap();
+
+        let mut progress = progress.lock().unwrap().clone();
+        progress.sort_unstable_by_key(|p| p.done);
+        assert_eq!(
+            progress,
+            [
+                Progress { done: 1, total: 2 },
+                Progress { done: 2, total: 2 }
+            ]
+        );
+        let csv = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+    }
+}
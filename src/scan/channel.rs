@@ -1,25 +1,29 @@
 use std::time::Duration;
 
-use once_cell::sync::Lazy;
 use pnet::datalink::{channel, Channel, Config, DataLinkReceiver, DataLinkSender};
 
-use crate::{abort, error::ScanError};
+use crate::error::ScanError;
 
-use super::interface;
+use super::interface::Board;
 
-static CHANNEL_CONFIG: Lazy<Config> = Lazy::new(|| Config {
-    read_timeout: Some(Duration::from_millis(1500)),
-    write_timeout: Some(Duration::from_millis(1500)),
-    ..Default::default()
-});
+type Link = (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>);
 
-pub fn link() -> (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>) {
-    let interface = &interface::DEFAULT;
-    let config = *CHANNEL_CONFIG;
+// Upper bound of how long reads and writes block.
+const MAX_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Opens a channel on `interface` whose reads and writes block for up to
+/// `timeout`, so that waiting on it never outlasts a probe.
+pub fn link(interface: &Board, timeout: Duration) -> Result<Link, ScanError> {
+    let timeout = timeout.min(MAX_TIMEOUT);
+    let config = Config {
+        read_timeout: Some(timeout),
+        write_timeout: Some(timeout),
+        ..Default::default()
+    };
 
     match channel(interface.raw(), config) {
-        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(Channel::Ethernet(tx, rx)) => Ok((tx, rx)),
         Ok(_) => unreachable!(),
-        Err(e) => abort(ScanError::DatalinkChannelFailed(e)),
+        Err(e) => Err(ScanError::DatalinkChannelFailed(e.into())),
     }
 }
@@ -0,0 +1,564 @@
+//! Raw probe engine shared by every scan that crafts its own TCP or UDP
+//! packets.
+//!
+//! A single datalink channel is opened for the whole scan. One thread sends
+//! probes and drives retransmissions, while another one reads every frame
+//! addressed to this host and hands replies to the probe they belong to,
+//! looked up by transport protocol, destination address, destination port
+//! and source port. Probes are resent after a timeout adapted to the
+//! round-trip times measured for their host.
+//!
+//! Every frame goes through the global rate limiter and, if the scan asks
+//! for congestion control, new probes wait until the congestion window
+//! admits them.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::ErrorKind,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::OnceCell;
+use pnet::{
+    datalink::{DataLinkReceiver, DataLinkSender},
+    packet::{
+        ethernet::EthernetPacket,
+        icmp::{IcmpCode, IcmpPacket, IcmpType, IcmpTypes},
+        icmpv6::{Icmpv6Code, Icmpv6Packet, Icmpv6Type, Icmpv6Types},
+        ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+        tcp::TcpPacket,
+        udp::UdpPacket,
+        Packet,
+    },
+    util::MacAddr,
+};
+
+use crate::{
+    error::ScanError,
+    scan::{
+        channel,
+        interface::{self, Board},
+        ndp, pckt,
+        rate::{CongestionWindow, LIMITER},
+        rtt::RttEstimator,
+        ScanOptions,
+    },
+};
+
+// Granularity in which pending probes are checked for expired timeouts.
+const SWEEP_INTERVAL: Duration = Duration::from_millis(50);
+
+// How often queued probes are checked while the congestion window is full.
+const ADMIT_INTERVAL: Duration = Duration::from_millis(1);
+
+// Channel timeout if the scan doesn't set one.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1500);
+
+static ENGINE: OnceCell<Engine> = OnceCell::new();
+
+/// Starts the engine shared by the whole process, unless it's running
+/// already, with a channel that never blocks for longer than the timeout of
+/// `options` and the congestion control it asks for.
+pub fn start(options: &ScanOptions) -> Result<&'static Engine, ScanError> {
+    ENGINE.get_or_try_init(|| Engine::start(options))
+}
+
+/// Returns the running engine, started with the default options if needed.
+pub fn get() -> Result<&'static Engine, ScanError> {
+    start(&ScanOptions::default())
+}
+
+/// What was received in response to a probe.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// Flags and window of a TCP segment.
+    Tcp {
+        flags: u8,
+        window: u16,
+    },
+    Udp,
+    Icmp(IcmpType, IcmpCode),
+    Icmpv6(Icmpv6Type, Icmpv6Code),
+    /// MAC address of the host that answered an NDP solicitation.
+    Neighbor(MacAddr),
+    /// Nothing arrived after every attempt.
+    Timeout,
+    /// The probe couldn't be sent in time or its next hop is unknown.
+    Unsent,
+    /// The probe couldn't be built or sent, or its reply might have been
+    /// lost.
+    Failed(ScanError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ProbeKey {
+    protocol: IpNextHeaderProtocol,
+    ip: IpAddr,
+    port: u16,
+    source_port: u16,
+}
+
+struct Pending {
+    frame: Arc<[u8]>,
+    attempts_left: usize,
+    /// Upper bound of the adaptive timeout.
+    timeout: Duration,
+    /// Last time the probe was sent, if it isn't waiting to be resent.
+    sent_at: Option<Instant>,
+    resent: bool,
+    reply: SyncSender<Reply>,
+}
+
+// A key without probe is reserved while its frame is being built.
+type PendingTable = Arc<Mutex<HashMap<ProbeKey, Option<Pending>>>>;
+
+type RttTable = Arc<Mutex<HashMap<IpAddr, RttEstimator>>>;
+
+// Only set when the scan asks for congestion control.
+type Window = Option<Arc<Mutex<CongestionWindow>>>;
+
+/// When new probes are let out of the queue.
+struct Admission {
+    window: Window,
+    /// Probes are sent at least this often, even if the window is full.
+    min_interval: Option<Duration>,
+}
+
+pub struct Engine {
+    pending: PendingTable,
+    outgoing: Sender<ProbeKey>,
+}
+
+impl Engine {
+    fn start(options: &ScanOptions) -> Result<Self, ScanError> {
+        let interface = interface::default()?;
+        let (sender, receiver) =
+            channel::link(interface, options.timeout.unwrap_or(DEFAULT_TIMEOUT))?;
+        let (outgoing, queue) = mpsc::channel();
+        let pending = PendingTable::default();
+        let rtt = RttTable::default();
+        let window = options
+            .congestion_control
+            .then(|| Arc::new(Mutex::new(CongestionWindow::default())));
+        let admission = Admission {
+            window: window.clone(),
+            min_interval: options
+                .min_rate
+                .map(|rate| Duration::from_secs_f64(1.0 / rate as f64)),
+        };
+
+        let (table, estimates) = (pending.clone(), rtt.clone());
+        thread::spawn(move || transmit(sender, queue, table, estimates, admission));
+
+        let table = pending.clone();
+        thread::spawn(move || demultiplex(interface, receiver, table, rtt, window));
+
+        log::debug!("Started raw probe engine");
+
+        Ok(Self { pending, outgoing })
+    }
+
+    /// Queues the frame returned by `build` for a random source port, to be
+    /// resent up to `retries` times after at most `timeout`, and returns
+    /// where its reply or timeout will be delivered. Nothing is sent if
+    /// `build` can't resolve the next hop or fails.
+    pub fn probe<F>(
+        &self,
+        addr: &SocketAddr,
+        protocol: IpNextHeaderProtocol,
+        retries: usize,
+        timeout: Duration,
+        build: F,
+    ) -> Receiver<Reply>
+    where
+        F: FnOnce(u16) -> Result<Option<EthernetPacket<'static>>, ScanError>,
+    {
+        let key = {
+            let mut pending = self.pending.lock().unwrap();
+
+            let key = loop {
+                let key = ProbeKey {
+                    protocol,
+                    ip: addr.ip(),
+                    port: addr.port(),
+                    source_port: rand::random(),
+                };
+                if !pending.contains_key(&key) {
+                    break key;
+                }
+            };
+            pending.insert(key, None);
+
+            key
+        };
+
+        // Resolving the next hop may block, so the table isn't held meanwhile.
+        let unsent = match build(key.source_port) {
+            Ok(Some(frame)) => return self.queue(key, retries, timeout, frame),
+            Ok(None) => Reply::Unsent,
+            Err(e) => Reply::Failed(e),
+        };
+        self.pending.lock().unwrap().remove(&key);
+
+        let (reply, result) = mpsc::sync_channel(1);
+        let _ = reply.send(unsent);
+        result
+    }
+
+    /// Queues an NDP solicitation of `kind` for `target`, which must not
+    /// have another one in flight, and returns where the MAC address of
+    /// whoever answers it will be delivered.
+    pub fn solicit(
+        &self,
+        target: Ipv6Addr,
+        kind: Icmpv6Type,
+        retries: usize,
+        timeout: Duration,
+        frame: EthernetPacket<'static>,
+    ) -> Receiver<Reply> {
+        let key = ProbeKey {
+            protocol: IpNextHeaderProtocols::Icmpv6,
+            ip: IpAddr::V6(target),
+            port: kind.0 as u16,
+            source_port: 0,
+        };
+
+        self.queue(key, retries, timeout, frame)
+    }
+
+    fn queue(
+        &self,
+        key: ProbeKey,
+        retries: usize,
+        timeout: Duration,
+        frame: EthernetPacket<'static>,
+    ) -> Receiver<Reply> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.pending.lock().unwrap().insert(
+            key,
+            Some(Pending {
+                frame: frame.packet().into(),
+                attempts_left: retries,
+                timeout,
+                sent_at: None,
+                resent: false,
+                reply,
+            }),
+        );
+
+        self.outgoing.send(key).unwrap();
+
+        result
+    }
+}
+
+fn send(sender: &mut dyn DataLinkSender, table: &PendingTable, key: ProbeKey) {
+    let Some(frame) = table
+        .lock()
+        .unwrap()
+        .get(&key)
+        .and_then(Option::as_ref)
+        .map(|p| p.frame.clone())
+    else {
+        return; // Already answered.
+    };
+
+    LIMITER.acquire();
+
+    match sender.send_to(&frame, None).unwrap() {
+        Ok(_) => {
+            log::debug!("Sent probe to port `{}` of `{}`", key.port, key.ip);
+
+            if let Some(Some(pending)) = table.lock().unwrap().get_mut(&key) {
+                pending.sent_at = Some(Instant::now());
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::TimedOut => {
+            if let Some(Some(pending)) = table.lock().unwrap().remove(&key) {
+                let _ = pending.reply.send(Reply::Unsent);
+            }
+        }
+        Err(e) => {
+            let e = ScanError::PacketSendFailed(key.ip, e.into());
+            log::debug!("{}", e);
+
+            if let Some(Some(pending)) = table.lock().unwrap().remove(&key) {
+                let _ = pending.reply.send(Reply::Failed(e));
+            }
+        }
+    }
+}
+
+fn transmit(
+    mut sender: Box<dyn DataLinkSender>,
+    queue: Receiver<ProbeKey>,
+    table: PendingTable,
+    rtt: RttTable,
+    admission: Admission,
+) {
+    let mut last_sweep = Instant::now();
+    let mut last_sent = Instant::now();
+    // Probes that the congestion window didn't admit yet.
+    let mut backlog = VecDeque::new();
+
+    loop {
+        let wait = match backlog.is_empty() {
+            true => SWEEP_INTERVAL,
+            false => ADMIT_INTERVAL,
+        };
+        match queue.recv_timeout(wait) {
+            Ok(key) => backlog.push_back(key),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        loop {
+            match queue.try_recv() {
+                Ok(key) => backlog.push_back(key),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        while !backlog.is_empty() {
+            // Queued probes are in the table as well.
+            let in_flight = table.lock().unwrap().len().saturating_sub(backlog.len());
+            let admitted = admission
+                .window
+                .as_ref()
+                .is_none_or(|window| window.lock().unwrap().admits(in_flight));
+            let overdue = admission
+                .min_interval
+                .is_some_and(|interval| last_sent.elapsed() >= interval);
+            if !admitted && !overdue {
+                break;
+            }
+
+            let key = backlog.pop_front().unwrap();
+            send(sender.as_mut(), &table, key);
+            last_sent = Instant::now();
+        }
+
+        if last_sweep.elapsed() < SWEEP_INTERVAL {
+            continue;
+        }
+        last_sweep = Instant::now();
+
+        // Probes without reply are either resent or given up, once the
+        // latest estimate of their host's timeout expires.
+        let mut expired = vec![];
+        let mut table_guard = table.lock().unwrap();
+        let estimates = rtt.lock().unwrap();
+        table_guard.retain(|&key, pending| {
+            let Some(pending) = pending else {
+                return true; // Still being built.
+            };
+
+            let timeout = estimates
+                .get(&key.ip)
+                .copied()
+                .unwrap_or_default()
+                .timeout(pending.timeout);
+            if pending
+                .sent_at
+                .is_none_or(|sent_at| sent_at + timeout > last_sweep)
+            {
+                return true;
+            }
+
+            if pending.attempts_left == 0 {
+                let _ = pending.reply.send(Reply::Timeout);
+                return false;
+            }
+
+            pending.attempts_left -= 1;
+            pending.resent = true;
+            pending.sent_at = None;
+            expired.push(key);
+
+            true
+        });
+        drop((table_guard, estimates));
+
+        // Drops mean that the host is slower than estimated or congested,
+        // which is accounted once per sweep.
+        let dropped = expired.iter().map(|key| key.ip).collect::<HashSet<_>>();
+        let mut estimates = rtt.lock().unwrap();
+        dropped
+            .into_iter()
+            .for_each(|ip| estimates.entry(ip).or_default().back_off());
+        drop(estimates);
+
+        if let (Some(window), false) = (&admission.window, expired.is_empty()) {
+            window.lock().unwrap().on_drop();
+        }
+
+        expired
+            .into_iter()
+            .for_each(|key| send(sender.as_mut(), &table, key));
+    }
+}
+
+/// Finds the probe that the received packet answers to. Only ICMP errors
+/// reporting an unreachable destination or an expired TTL are considered.
+fn match_reply(interface: &Board, raw: &[u8]) -> Option<(ProbeKey, Reply)> {
+    let ip_pckt = pckt::unwrap(raw)?;
+    // Router advertisements are usually multicast to every node.
+    if !interface.owns(&ip_pckt.destination) && !ip_pckt.destination.is_multicast() {
+        return None;
+    }
+
+    let (key, reply) = match ip_pckt.protocol {
+        IpNextHeaderProtocols::Tcp => {
+            let tcp_pckt = TcpPacket::new(ip_pckt.payload)?;
+            let key = ProbeKey {
+                protocol: ip_pckt.protocol,
+                ip: ip_pckt.source,
+                port: tcp_pckt.get_source(),
+                source_port: tcp_pckt.get_destination(),
+            };
+            let reply = Reply::Tcp {
+                flags: tcp_pckt.get_flags(),
+                window: tcp_pckt.get_window(),
+            };
+
+            (key, reply)
+        }
+        IpNextHeaderProtocols::Udp => {
+            let udp_pckt = UdpPacket::new(ip_pckt.payload)?;
+            let key = ProbeKey {
+                protocol: ip_pckt.protocol,
+                ip: ip_pckt.source,
+                port: udp_pckt.get_source(),
+                source_port: udp_pckt.get_destination(),
+            };
+
+            (key, Reply::Udp)
+        }
+        IpNextHeaderProtocols::Icmp => {
+            let icmp_pckt = IcmpPacket::new(ip_pckt.payload)?;
+            let icmp_type = icmp_pckt.get_icmp_type();
+            if icmp_type != IcmpTypes::DestinationUnreachable
+                && icmp_type != IcmpTypes::TimeExceeded
+            {
+                return None;
+            }
+
+            // Error messages have 4 unused bytes before the quoted packet.
+            let (ip, protocol, source_port, port) = pckt::quoted(icmp_pckt.payload().get(4..)?)?;
+            let key = ProbeKey {
+                protocol,
+                ip,
+                port,
+                source_port,
+            };
+            let reply = Reply::Icmp(icmp_type, icmp_pckt.get_icmp_code());
+
+            (key, reply)
+        }
+        IpNextHeaderProtocols::Icmpv6 => {
+            if let Some((target, kind)) = ndp::advertised(ip_pckt.payload) {
+                let key = ProbeKey {
+                    protocol: ip_pckt.protocol,
+                    ip: IpAddr::V6(target),
+                    port: kind.0 as u16,
+                    source_port: 0,
+                };
+                let reply = Reply::Neighbor(EthernetPacket::new(raw)?.get_source());
+
+                return Some((key, reply));
+            }
+
+            let icmpv6_pckt = Icmpv6Packet::new(ip_pckt.payload)?;
+            let icmpv6_type = icmpv6_pckt.get_icmpv6_type();
+            if icmpv6_type != Icmpv6Types::DestinationUnreachable
+                && icmpv6_type != Icmpv6Types::TimeExceeded
+            {
+                return None;
+            }
+
+            let (ip, protocol, source_port, port) = pckt::quoted(icmpv6_pckt.payload().get(4..)?)?;
+            let key = ProbeKey {
+                protocol,
+                ip,
+                port,
+                source_port,
+            };
+            let reply = Reply::Icmpv6(icmpv6_type, icmpv6_pckt.get_icmpv6_code());
+
+            (key, reply)
+        }
+        _ => return None,
+    };
+
+    Some((key, reply))
+}
+
+fn demultiplex(
+    interface: &Board,
+    mut receiver: Box<dyn DataLinkReceiver>,
+    table: PendingTable,
+    rtt: RttTable,
+    window: Window,
+) {
+    loop {
+        match receiver.next() {
+            Ok(raw) => {
+                let Some((key, reply)) = match_reply(interface, raw) else {
+                    continue;
+                };
+
+                let pending = {
+                    let mut table = table.lock().unwrap();
+                    match table.get(&key) {
+                        Some(Some(_)) => table.remove(&key).flatten(),
+                        _ => None, // Unknown or still being built.
+                    }
+                };
+
+                if let Some(pending) = pending {
+                    if let (Some(sent_at), false) = (pending.sent_at, pending.resent) {
+                        rtt.lock()
+                            .unwrap()
+                            .entry(key.ip)
+                            .or_default()
+                            .sample(sent_at.elapsed());
+                    }
+                    if let Some(window) = &window {
+                        window.lock().unwrap().on_reply();
+                    }
+
+                    log::debug!(
+                        "Received `{:?}` from port `{}` of `{}`",
+                        reply,
+                        key.port,
+                        key.ip
+                    );
+
+                    let _ = pending.reply.send(reply);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::TimedOut => (),
+            Err(e) => {
+                let e = ScanError::ChannelRecvFailed(e.into());
+                log::debug!("{}", e);
+
+                // Replies to the probes in flight might have been lost.
+                table.lock().unwrap().retain(|_, pending| match pending {
+                    Some(pending) => {
+                        let _ = pending.reply.send(Reply::Failed(e.clone()));
+                        false
+                    }
+                    None => true,
+                });
+
+                thread::sleep(SWEEP_INTERVAL);
+            }
+        }
+    }
+}
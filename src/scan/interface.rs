@@ -1,24 +1,35 @@
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use default_net::get_default_gateway;
-use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
 use pnet::{
     datalink::{interfaces, NetworkInterface},
-    ipnetwork::IpNetwork,
+    ipnetwork::{IpNetwork, Ipv6Network},
     util::MacAddr,
 };
 
-use crate::{abort, error::ScanError};
+use crate::error::ScanError;
 
 pub struct Board {
     mac: MacAddr,
-    ip: Ipv4Addr,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Network>,
     raw: NetworkInterface,
 }
 
 impl Board {
-    fn new(mac: MacAddr, ip: Ipv4Addr, raw: NetworkInterface) -> Self {
-        Self { mac, ip, raw }
+    fn new(
+        mac: MacAddr,
+        ipv4: Option<Ipv4Addr>,
+        ipv6: Option<Ipv6Network>,
+        raw: NetworkInterface,
+    ) -> Self {
+        Self {
+            mac,
+            ipv4,
+            ipv6,
+            raw,
+        }
     }
 
     #[inline]
@@ -27,50 +38,150 @@ impl Board {
     }
 
     #[inline]
-    pub fn ip(&self) -> Ipv4Addr {
-        self.ip
+    pub fn ipv4(&self) -> Result<Ipv4Addr, ScanError> {
+        self.ipv4.ok_or(ScanError::MissingInterfaceIpv4)
+    }
+
+    #[inline]
+    pub fn ipv6(&self) -> Result<Ipv6Addr, ScanError> {
+        self.ipv6
+            .map(|ipnet| ipnet.ip())
+            .ok_or(ScanError::MissingInterfaceIpv6)
+    }
+
+    /// Returns the address of the same IP version as `dest`.
+    #[inline]
+    pub fn ip_for(&self, dest: &IpAddr) -> Result<IpAddr, ScanError> {
+        match dest {
+            IpAddr::V4(_) => self.ipv4().map(IpAddr::V4),
+            IpAddr::V6(_) => self.ipv6().map(IpAddr::V6),
+        }
+        .map_err(|_| ScanError::MissingSourceAddr(*dest))
+    }
+
+    /// Checks if there's an address to send packets to `dest` from.
+    #[inline]
+    pub fn can_reach(&self, dest: &IpAddr) -> bool {
+        match dest {
+            IpAddr::V4(_) => self.ipv4.is_some(),
+            IpAddr::V6(_) => self.ipv6.is_some(),
+        }
+    }
+
+    /// Checks if `ip` is the address the probes are sent from.
+    #[inline]
+    pub fn owns(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.ipv4 == Some(*ip),
+            IpAddr::V6(ip) => self.ipv6.is_some_and(|ipnet| ipnet.ip() == *ip),
+        }
+    }
+
+    /// Checks if `dest` can be reached without going through a router.
+    #[inline]
+    pub fn is_on_link(&self, dest: Ipv6Addr) -> bool {
+        dest.segments()[0] & 0xffc0 == 0xfe80 || self.ipv6.is_some_and(|ipnet| ipnet.contains(dest))
     }
 
     #[inline]
     pub fn raw(&self) -> &NetworkInterface {
         &self.raw
     }
+
+    /// Checks if both send from the same interface and addresses.
+    fn same_as(&self, other: &Board) -> bool {
+        self.raw.name == other.raw.name && self.ipv4 == other.ipv4 && self.ipv6 == other.ipv6
+    }
 }
 
-pub static DEFAULT: Lazy<Board> = Lazy::new(|| {
+static DEFAULT: OnceCell<Board> = OnceCell::new();
+
+static GATEWAY: OnceCell<MacAddr> = OnceCell::new();
+
+/// Returns the interface that probes are sent through, which is looked up
+/// again on every call until it's found.
+pub fn default() -> Result<&'static Board, ScanError> {
+    DEFAULT.get_or_try_init(|| find(None, None))
+}
+
+/// Sends probes through the interface called `name`, or the first one up,
+/// and from `source` instead of its address of the same IP version. Only
+/// one interface is used by the whole process, so it can't be changed once
+/// any probe was sent.
+pub fn select(name: Option<&str>, source: Option<IpAddr>) -> Result<&'static Board, ScanError> {
+    let mut board = Some(find(name, source)?);
+    let selected = DEFAULT.get_or_init(|| board.take().unwrap());
+
+    match board {
+        Some(board) if !board.same_as(selected) => {
+            Err(ScanError::InterfaceInUse(selected.raw.name.clone()))
+        }
+        _ => Ok(selected),
+    }
+}
+
+/// Returns the MAC address of the default gateway, which is looked up
+/// again on every call until it's found.
+pub fn gateway() -> Result<MacAddr, ScanError> {
+    GATEWAY.get_or_try_init(find_gateway).copied()
+}
+
+fn find(name: Option<&str>, source: Option<IpAddr>) -> Result<Board, ScanError> {
     let default = interfaces()
-        .iter()
-        .find(|e| e.is_up() && !e.is_loopback() && !e.ips.is_empty())
-        .cloned()
-        .unwrap_or_else(|| abort(ScanError::MissingDefaultInterface));
+        .into_iter()
+        .find(|e| match name {
+            Some(name) => e.name == name,
+            None => e.is_up() && !e.is_loopback() && !e.ips.is_empty(),
+        })
+        .ok_or_else(|| match name {
+            Some(name) => ScanError::InterfaceNotFound(name.into()),
+            None => ScanError::MissingDefaultInterface,
+        })?;
 
-    let mac = match default.mac {
-        Some(addr) => addr,
-        None => abort(ScanError::MissingMacAddr),
-    };
+    let mac = default.mac.ok_or(ScanError::MissingMacAddr)?;
+
+    let ipv4 = default.ips.iter().find_map(|ip| match ip {
+        IpNetwork::V4(ipnet) => Some(ipnet.ip()),
+        IpNetwork::V6(_) => None,
+    });
+
+    // Global addresses take precedence over link-local ones.
+    let ipv6 = default
+        .ips
+        .iter()
+        .filter_map(|ip| match ip {
+            IpNetwork::V6(ipnet) => Some(*ipnet),
+            IpNetwork::V4(_) => None,
+        })
+        .min_by_key(|ipnet| ipnet.ip().segments()[0] & 0xffc0 == 0xfe80);
 
-    let ip = match default.ips.iter().find(|ip| ip.is_ipv4()) {
-        Some(IpNetwork::V4(ipnet)) => ipnet.ip(),
-        _ => abort(ScanError::OnlyIpv4InterfaceSupported),
+    // The source address keeps the prefix of the one it replaces.
+    let (ipv4, ipv6) = match source {
+        Some(IpAddr::V4(ip)) => (Some(ip), ipv6),
+        Some(IpAddr::V6(ip)) => {
+            let prefix = ipv6.map_or(128, |ipnet| ipnet.prefix());
+            (ipv4, Some(Ipv6Network::new(ip, prefix).unwrap()))
+        }
+        None => (ipv4, ipv6),
     };
 
     log::debug!(
-        "Using network interface `{}` with MAC address `{}` and IPv4 address `{}`",
+        "Using network interface `{}` with MAC address `{}`, IPv4 address `{:?}` and IPv6 address `{:?}`",
         default.name,
         mac,
-        ip
+        ipv4,
+        ipv6.map(|ipnet| ipnet.ip()),
     );
 
-    Board::new(mac, ip, default)
-});
+    Ok(Board::new(mac, ipv4, ipv6, default))
+}
 
-pub static GATEWAY: Lazy<MacAddr> = Lazy::new(|| match get_default_gateway() {
-    Ok(gateway) => {
-        let mac = gateway.mac_addr;
+fn find_gateway() -> Result<MacAddr, ScanError> {
+    let mac = get_default_gateway()
+        .map_err(ScanError::GatewayLookupFailed)?
+        .mac_addr;
 
-        log::debug!("Found gateway MAC address `{}`", mac);
+    log::debug!("Found gateway MAC address `{}`", mac);
 
-        MacAddr::new(mac.0, mac.1, mac.2, mac.3, mac.4, mac.5)
-    }
-    Err(e) => abort(ScanError::GatewayLookupFailed(e)),
-});
+    Ok(MacAddr::new(mac.0, mac.1, mac.2, mac.3, mac.4, mac.5))
+}
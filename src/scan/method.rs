@@ -1,5 +1,15 @@
+mod ack;
+mod fin;
+mod stateless;
 mod syn;
 mod tcp;
+mod udp;
 
+pub(super) use ack::AckScan;
+pub(super) use fin::FinScan;
+pub(super) use stateless::StatelessSynScan;
 pub(super) use syn::SynScan;
 pub(super) use tcp::TcpScan;
+#[cfg(feature = "async")]
+pub(super) use tcp::{connect, TIMEOUT as TCP_TIMEOUT};
+pub(super) use udp::UdpScan;
@@ -0,0 +1,148 @@
+use std::net::SocketAddr;
+
+use pnet::packet::{ip::IpNextHeaderProtocols, tcp::TcpFlags};
+
+use crate::scan::{engine::Reply, Executor, PortState, Probe, Reason, ScanOptions, Verdict};
+
+use super::syn::{build_tcp, icmp_verdict, source, TcpKnownFlags, SEND_RETRIES, SEND_TIMOUT};
+
+/// Scan sending bare ACK segments, which open and closed ports alike answer
+/// with a reset, so that it only maps which ports a firewall filters. Based
+/// on https://nmap.org/book/scan-methods-ack-scan.html
+#[derive(Debug)]
+pub struct AckScan {
+    /// Whether the window of resets tells open ports from closed ones.
+    window: bool,
+}
+
+impl AckScan {
+    /// Only tells filtered ports from unfiltered ones.
+    pub fn ack() -> Self {
+        Self { window: false }
+    }
+
+    /// Reads the window of resets, which some stacks leave positive for
+    /// open ports and zero for closed ones. Based on
+    /// https://nmap.org/book/scan-methods-window-scan.html
+    pub fn window() -> Self {
+        Self { window: true }
+    }
+}
+
+impl Executor for AckScan {
+    fn scan(&self, addr: &SocketAddr, options: &ScanOptions) -> Probe {
+        let (engine, source_ip) = match source(&addr.ip()) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
+        };
+
+        let destination_port = addr.port();
+
+        let build = |source_port| build_tcp(source_ip, addr, source_port, 0, TcpFlags::ACK);
+        let retries = options.retries.unwrap_or(SEND_RETRIES);
+
+        let reply = engine.probe(
+            addr,
+            IpNextHeaderProtocols::Tcp,
+            retries,
+            options.timeout.unwrap_or(SEND_TIMOUT),
+            build,
+        );
+
+        let window = self.window;
+
+        Probe::Waiting(Box::new(move || {
+            verdict(
+                reply.recv().unwrap_or(Reply::Unsent),
+                destination_port,
+                retries,
+                window,
+            )
+        }))
+    }
+}
+
+/// Tells whether `port` is filtered from the reply to its probe and, when
+/// reading the `window` of resets, whether it's open.
+fn verdict(reply: Reply, port: u16, retries: usize, window: bool) -> Verdict {
+    let verdict = match reply {
+        Reply::Tcp {
+            flags,
+            window: size,
+        } => {
+            log::debug!(
+                "Received `{}` TCP packet from port `{}` with window `{}`",
+                TcpKnownFlags(flags),
+                port,
+                size,
+            );
+
+            // Stacks only ever reset unexpected ACKs, so anything else came
+            // from a middlebox that doesn't let the probe through.
+            match (flags & TcpFlags::RST, window, size) {
+                (0, ..) => (PortState::Filtered, Reason::NoResponse(0)),
+                (_, false, _) => (PortState::Unfiltered, Reason::Rst),
+                (_, true, 0) => (PortState::Closed, Reason::Rst),
+                (_, true, _) => (PortState::Open, Reason::Rst),
+            }
+        }
+        // Whatever the ICMP error says, the probe didn't reach the port.
+        Reply::Icmp(..) | Reply::Icmpv6(..) => (PortState::Filtered, icmp_verdict(&reply, port).1),
+        Reply::Udp | Reply::Neighbor(_) => unreachable!(),
+        Reply::Timeout => (PortState::Filtered, Reason::NoResponse(retries)),
+        Reply::Unsent => (PortState::Unknown, Reason::Unsent),
+        Reply::Failed(e) => return Err(e),
+    };
+
+    Ok(verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::icmp::{destination_unreachable::IcmpCodes, IcmpTypes};
+
+    use super::*;
+
+    #[test]
+    fn resets_mean_unfiltered() {
+        let state = |reply| verdict(reply, 80, 2, false).unwrap();
+
+        assert_eq!(
+            state(Reply::Tcp {
+                flags: TcpFlags::RST,
+                window: 0
+            }),
+            (PortState::Unfiltered, Reason::Rst)
+        );
+        assert_eq!(
+            state(Reply::Timeout),
+            (PortState::Filtered, Reason::NoResponse(2))
+        );
+        assert_eq!(
+            state(Reply::Icmp(
+                IcmpTypes::DestinationUnreachable,
+                IcmpCodes::CommunicationAdministrativelyProhibited
+            )),
+            (PortState::Filtered, Reason::IcmpUnreach)
+        );
+    }
+
+    #[test]
+    fn window_of_resets_tells_open_ports() {
+        let state = |window| {
+            verdict(
+                Reply::Tcp {
+                    flags: TcpFlags::RST,
+                    window,
+                },
+                80,
+                2,
+                true,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(state(1024), (PortState::Open, Reason::Rst));
+        assert_eq!(state(0), (PortState::Closed, Reason::Rst));
+    }
+}
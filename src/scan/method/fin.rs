@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+
+use pnet::packet::{ip::IpNextHeaderProtocols, tcp::TcpFlags};
+
+use crate::scan::{engine::Reply, Executor, PortState, Probe, Reason, ScanOptions, Verdict};
+
+use super::syn::{build_tcp, icmp_verdict, source, TcpKnownFlags, SEND_RETRIES, SEND_TIMOUT};
+
+/// Scan sending segments without SYN, RST or ACK, which RFC 793 has closed
+/// ports answer with a reset and open ones drop. Based on
+/// https://nmap.org/book/scan-methods-null-fin-xmas-scan.html
+#[derive(Debug)]
+pub struct FinScan {
+    flags: u8,
+}
+
+impl FinScan {
+    /// Sends FIN segments.
+    pub fn fin() -> Self {
+        Self {
+            flags: TcpFlags::FIN,
+        }
+    }
+
+    /// Sends segments without any flag.
+    pub fn null() -> Self {
+        Self { flags: 0 }
+    }
+
+    /// Sends FIN, PSH and URG segments, lit up like a Christmas tree.
+    pub fn xmas() -> Self {
+        Self {
+            flags: TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG,
+        }
+    }
+
+    /// Sends segments with `flags`, whose replies are read as if they were
+    /// FIN segments.
+    pub fn with_flags(flags: u8) -> Self {
+        Self { flags }
+    }
+
+    /// Sends FIN and ACK segments, which many BSD-derived stacks drop
+    /// instead of resetting when the port is open. Based on
+    /// https://nmap.org/book/scan-methods-maimon-scan.html
+    pub fn maimon() -> Self {
+        Self {
+            flags: TcpFlags::FIN | TcpFlags::ACK,
+        }
+    }
+}
+
+impl Executor for FinScan {
+    fn scan(&self, addr: &SocketAddr, options: &ScanOptions) -> Probe {
+        let (engine, source_ip) = match source(&addr.ip()) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
+        };
+
+        let destination_port = addr.port();
+
+        let build = |source_port| build_tcp(source_ip, addr, source_port, 0, self.flags);
+        let retries = options.retries.unwrap_or(SEND_RETRIES);
+
+        let reply = engine.probe(
+            addr,
+            IpNextHeaderProtocols::Tcp,
+            retries,
+            options.timeout.unwrap_or(SEND_TIMOUT),
+            build,
+        );
+
+        Probe::Waiting(Box::new(move || {
+            verdict(
+                reply.recv().unwrap_or(Reply::Unsent),
+                destination_port,
+                retries,
+            )
+        }))
+    }
+}
+
+/// Tells the state of `port` from the reply to its probe.
+fn verdict(reply: Reply, port: u16, retries: usize) -> Verdict {
+    let verdict = match reply {
+        Reply::Tcp { flags, .. } => {
+            log::debug!(
+                "Received `{}` TCP packet from port `{}`",
+                TcpKnownFlags(flags),
+                port,
+            );
+
+            // Anything but a reset is as good as no reply, since open ports
+            // don't answer at all.
+            match flags & TcpFlags::RST {
+                0 => (PortState::OpenFiltered, Reason::NoResponse(0)),
+                _ => (PortState::Closed, Reason::Rst),
+            }
+        }
+        Reply::Icmp(..) | Reply::Icmpv6(..) => icmp_verdict(&reply, port),
+        Reply::Udp | Reply::Neighbor(_) => unreachable!(),
+        Reply::Timeout => (PortState::OpenFiltered, Reason::NoResponse(retries)),
+        Reply::Unsent => (PortState::Unknown, Reason::Unsent),
+        Reply::Failed(e) => return Err(e),
+    };
+
+    Ok(verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::icmp::{destination_unreachable::IcmpCodes, IcmpTypes};
+
+    use super::*;
+
+    #[test]
+    fn only_resets_mean_closed() {
+        let state = |reply| verdict(reply, 80, 2).unwrap();
+
+        assert_eq!(
+            state(Reply::Tcp {
+                flags: TcpFlags::RST | TcpFlags::ACK,
+                window: 0
+            }),
+            (PortState::Closed, Reason::Rst)
+        );
+        assert_eq!(
+            state(Reply::Timeout),
+            (PortState::OpenFiltered, Reason::NoResponse(2))
+        );
+        assert_eq!(
+            state(Reply::Icmp(
+                IcmpTypes::DestinationUnreachable,
+                IcmpCodes::CommunicationAdministrativelyProhibited
+            )),
+            (PortState::Filtered, Reason::IcmpUnreach)
+        );
+    }
+}
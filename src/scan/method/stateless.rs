@@ -0,0 +1,289 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use pnet::{
+    datalink::{DataLinkReceiver, DataLinkSender},
+    packet::{
+        ip::IpNextHeaderProtocols,
+        tcp::{TcpFlags, TcpPacket},
+        Packet,
+    },
+};
+use rayon::prelude::ParallelIterator;
+
+use crate::{
+    error::ScanError,
+    scan::{
+        channel,
+        interface::{self, Board},
+        ndp, pckt,
+        rate::LIMITER,
+        Executor, PortState, PortsToScan, Probe, Reason, ScanOptions, Verdict,
+    },
+};
+
+use super::syn::build_tcp;
+
+// Time to wait for late replies once every probe was sent, unless the
+// scan sets a timeout.
+const GRACE_PERIOD: Duration = Duration::from_millis(3000);
+
+// Source ports are picked from the dynamic range.
+const SOURCE_PORT_BASE: u16 = 49152;
+
+/// Keyed hash of a probe, sent as its sequence number, so that replies can
+/// be validated without remembering what was sent.
+#[derive(Debug)]
+struct Cookie(RandomState);
+
+impl Cookie {
+    fn new() -> Self {
+        Self(Default::default())
+    }
+
+    fn of(&self, addr: &SocketAddr, source_port: u16) -> u32 {
+        self.0.hash_one((addr.ip(), addr.port(), source_port)) as u32
+    }
+
+    /// Checks if `ack` acknowledges the probe sent to `addr`.
+    fn acknowledges(&self, ack: u32, addr: &SocketAddr, source_port: u16) -> bool {
+        ack == self.of(addr, source_port).wrapping_add(1)
+    }
+}
+
+/// SYN scan that sends every probe up front at a fixed rate, based on
+/// https://github.com/robertdavidgraham/masscan, and keeps no state for
+/// them. Probes are never resent, and like masscan, ports that didn't
+/// answer are considered closed.
+#[derive(Debug)]
+pub struct StatelessSynScan {
+    rate: u32,
+    cookie: Cookie,
+    source_port: u16,
+    replies: Mutex<HashMap<SocketAddr, Verdict>>,
+    // Why replies stopped being received, if they did.
+    failure: Mutex<Option<ScanError>>,
+}
+
+impl StatelessSynScan {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            cookie: Cookie::new(),
+            source_port: SOURCE_PORT_BASE + rand::random::<u16>() % (u16::MAX - SOURCE_PORT_BASE),
+            replies: Default::default(),
+            failure: Default::default(),
+        }
+    }
+
+    fn send_all(
+        &self,
+        interface: &Board,
+        sender: &mut dyn DataLinkSender,
+        ips: &[IpAddr],
+        ports: &[u16],
+    ) {
+        let rate = self.rate as f64;
+        let start = Instant::now();
+
+        // Hosts are interleaved so that none of them is flooded.
+        let targets = ports
+            .iter()
+            .flat_map(|&port| ips.iter().map(move |&ip| SocketAddr::new(ip, port)));
+
+        for (sent, addr) in targets.enumerate() {
+            let due = Duration::from_secs_f64(sent as f64 / rate);
+            if let Some(ahead) = due.checked_sub(start.elapsed()) {
+                thread::sleep(ahead);
+            }
+            // The scan's maximum rate applies on top of the technique's.
+            LIMITER.acquire();
+
+            let sequence = self.cookie.of(&addr, self.source_port);
+            let built = interface.ip_for(&addr.ip()).and_then(|source_ip| {
+                build_tcp(source_ip, &addr, self.source_port, sequence, TcpFlags::SYN)
+            });
+
+            let verdict = match built {
+                Ok(Some(ethernet_pckt)) => match sender.send_to(ethernet_pckt.packet(), None) {
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) if e.kind() != ErrorKind::TimedOut => {
+                        Err(ScanError::PacketSendFailed(addr.ip(), e.into()))
+                    }
+                    _ => Ok((PortState::Unknown, Reason::Unsent)),
+                },
+                Ok(None) => Ok((PortState::Unknown, Reason::Unsent)),
+                Err(e) => Err(e),
+            };
+            self.replies.lock().unwrap().insert(addr, verdict);
+        }
+
+        log::debug!(
+            "Sent stateless SYN probes in {:.4}s",
+            start.elapsed().as_secs_f32()
+        );
+    }
+
+    fn receive_all(
+        &self,
+        interface: &Board,
+        receiver: &mut dyn DataLinkReceiver,
+        done: &AtomicBool,
+        grace: Duration,
+    ) -> Result<(), ScanError> {
+        let mut finished = None;
+        loop {
+            match (finished, done.load(Ordering::Acquire)) {
+                (None, true) => finished = Some(Instant::now()),
+                (Some(at), _) if at.elapsed() > grace => return Ok(()),
+                _ => (),
+            }
+
+            match receiver.next() {
+                Ok(raw) => {
+                    if let Some((addr, verdict)) = self.match_reply(interface, raw) {
+                        log::debug!("Received `{}` from port `{}`", verdict.1, addr);
+
+                        self.replies.lock().unwrap().insert(addr, Ok(verdict));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => (),
+                Err(e) => return Err(ScanError::ChannelRecvFailed(e.into())),
+            }
+        }
+    }
+
+    fn match_reply(
+        &self,
+        interface: &Board,
+        raw: &[u8],
+    ) -> Option<(SocketAddr, (PortState, Reason))> {
+        let ip_pckt = pckt::unwrap(raw)?;
+        if ip_pckt.protocol != IpNextHeaderProtocols::Tcp || !interface.owns(&ip_pckt.destination) {
+            return None;
+        }
+
+        let tcp_pckt = TcpPacket::new(ip_pckt.payload)?;
+        let addr = SocketAddr::new(ip_pckt.source, tcp_pckt.get_source());
+        if tcp_pckt.get_destination() != self.source_port
+            || !self
+                .cookie
+                .acknowledges(tcp_pckt.get_acknowledgement(), &addr, self.source_port)
+        {
+            return None;
+        }
+
+        let verdict = match tcp_pckt.get_flags() {
+            flags if flags & TcpFlags::RST != 0 => (PortState::Closed, Reason::Rst),
+            flags if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK => {
+                (PortState::Open, Reason::SynAck)
+            }
+            _ => return None,
+        };
+
+        Some((addr, verdict))
+    }
+}
+
+impl Executor for StatelessSynScan {
+    fn prepare(
+        &self,
+        ips: &[IpAddr],
+        ports: &PortsToScan,
+        options: &ScanOptions,
+    ) -> Result<(), ScanError> {
+        let interface = interface::default()?;
+        let ports = ports.par_iter().collect::<Vec<_>>();
+        let done = AtomicBool::new(false);
+
+        // Silent neighbors would stall sending otherwise.
+        ndp::resolve(ips)?;
+
+        let grace = options.timeout.unwrap_or(GRACE_PERIOD);
+
+        // Opened before sending so that no early reply is missed.
+        let (mut sender, mut receiver) = channel::link(interface, grace)?;
+
+        let received = thread::scope(|s| {
+            let receiving =
+                s.spawn(|| self.receive_all(interface, receiver.as_mut(), &done, grace));
+
+            self.send_all(interface, sender.as_mut(), ips, &ports);
+            done.store(true, Ordering::Release);
+
+            receiving.join().unwrap()
+        });
+
+        // Ports that never answered can't be told closed anymore.
+        if let Err(e) = received {
+            log::debug!("Stopped receiving stateless SYN replies: {e}");
+            *self.failure.lock().unwrap() = Some(e);
+        }
+
+        Ok(())
+    }
+
+    fn scan(&self, addr: &SocketAddr, _options: &ScanOptions) -> Probe {
+        let reply = self.replies.lock().unwrap().get(addr).cloned();
+
+        match reply {
+            Some(verdict) => Probe::Done(verdict),
+            None => match self.failure.lock().unwrap().clone() {
+                Some(e) => e.into(),
+                None => (PortState::Closed, Reason::NoResponse(0)).into(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn cookie_acknowledged_by_next_sequence() {
+        let cookie = Cookie::new();
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 80);
+        let ack = cookie.of(&addr, 50000).wrapping_add(1);
+
+        assert_eq!(cookie.of(&addr, 50000), cookie.of(&addr, 50000));
+        assert!(cookie.acknowledges(ack, &addr, 50000));
+        assert!(!cookie.acknowledges(ack.wrapping_sub(1), &addr, 50000));
+    }
+
+    #[test]
+    fn cookie_depends_on_probe() {
+        let cookie = Cookie::new();
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 80);
+        let ack = cookie.of(&addr, 50000).wrapping_add(1);
+
+        let other_ip = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 80);
+        let other_port = SocketAddr::new(addr.ip(), 81);
+
+        assert!(!cookie.acknowledges(ack, &other_ip, 50000));
+        assert!(!cookie.acknowledges(ack, &other_port, 50000));
+        assert!(!cookie.acknowledges(ack, &addr, 50001));
+    }
+
+    #[test]
+    fn cookie_keys_differ() {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 80);
+
+        assert_ne!(
+            Cookie::new().of(&addr, 50000),
+            Cookie::new().of(&addr, 50000)
+        );
+    }
+}
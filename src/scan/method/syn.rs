@@ -1,36 +1,40 @@
 use std::{
     fmt::Display,
-    io::ErrorKind,
-    net::{IpAddr, SocketAddrV4},
-    time::{Duration, Instant},
+    net::{IpAddr, SocketAddr},
+    time::Duration,
 };
 
 use pnet::packet::{
-    ethernet::{EtherTypes, EthernetPacket},
-    icmp::{destination_unreachable::IcmpCodes, IcmpCode, IcmpPacket, IcmpTypes},
+    ethernet::EthernetPacket,
+    icmp::{destination_unreachable::IcmpCodes, IcmpCode, IcmpTypes},
+    icmpv6::{Icmpv6Code, Icmpv6Types},
     ip::IpNextHeaderProtocols,
-    ipv4::Ipv4Packet,
-    tcp::{ipv4_checksum, MutableTcpPacket, TcpFlags, TcpOption, TcpPacket},
+    tcp::{ipv4_checksum, ipv6_checksum, MutableTcpPacket, TcpFlags, TcpOption},
     Packet,
 };
 
 use crate::{
-    abort,
     error::ScanError,
-    scan::{channel, interface, pckt, Executor, PortState},
+    scan::{
+        engine::{self, Engine, Reply},
+        interface, pckt, Executor, PortState, Probe, Reason, ScanOptions,
+    },
 };
 
-const SEND_ATTEMPTS: usize = 3;
-const SEND_TIMOUT: Duration = Duration::from_millis(4000);
+pub(super) const SEND_RETRIES: usize = 3;
+pub(super) const SEND_TIMOUT: Duration = Duration::from_millis(4000);
 
 const TCP_PKT_SZ: usize = 40;
 const TCP_HDR_SZ: u8 = TCP_PKT_SZ as u8;
 const TCP_HDR_WORDS: u8 = TCP_HDR_SZ / 4;
 
+// Segments without SYN carry no options.
+const TCP_BARE_HDR_WORDS: u8 = 5;
+
 const SYN_ACK: u8 = TcpFlags::SYN | TcpFlags::ACK;
 const RST_ACK: u8 = TcpFlags::RST | TcpFlags::ACK;
 
-const ICMP_TYPE_3_CODES: &[IcmpCode] = &[
+pub(super) const ICMP_TYPE_3_CODES: &[IcmpCode] = &[
     IcmpCodes::DestinationHostUnreachable,
     IcmpCodes::DestinationProtocolUnreachable,
     IcmpCodes::DestinationPortUnreachable,
@@ -39,7 +43,16 @@ const ICMP_TYPE_3_CODES: &[IcmpCode] = &[
     IcmpCodes::CommunicationAdministrativelyProhibited,
 ];
 
-struct TcpKnownFlags(u8);
+// Codes from https://www.rfc-editor.org/rfc/rfc4443#section-3.1
+pub(super) const ICMPV6_TYPE_1_CODES: &[Icmpv6Code] = &[
+    Icmpv6Code(1), // Communication with destination administratively prohibited.
+    Icmpv6Code(3), // Address unreachable.
+    Icmpv6Code(4), // Port unreachable.
+    Icmpv6Code(5), // Source address failed ingress/egress policy.
+    Icmpv6Code(6), // Reject route to destination.
+];
+
+pub(super) struct TcpKnownFlags(pub(super) u8);
 
 impl TcpKnownFlags {
     #[inline]
@@ -58,31 +71,93 @@ impl Display for TcpKnownFlags {
     }
 }
 
-#[derive(Debug)]
-pub struct SynScan;
-
-impl Executor for SynScan {
-    fn scan(&self, addr: &SocketAddrV4) -> PortState {
-        let (mut sender, mut receiver) = channel::link();
+#[inline]
+fn icmp_reason(time_exceeded: bool) -> Reason {
+    match time_exceeded {
+        true => Reason::TimeExceeded,
+        false => Reason::IcmpUnreach,
+    }
+}
 
-        // Prepare SYN packet.
+/// Tells the state of a TCP port from an ICMP error sent in reply to its
+/// probe: filtered if the error is one of the unreachable codes listed,
+/// closed otherwise.
+pub(super) fn icmp_verdict(reply: &Reply, port: u16) -> (PortState, Reason) {
+    match *reply {
+        Reply::Icmp(icmp_type, icmp_code) => {
+            log::debug!(
+                "Received ICMP packet from port `{}` with type `{}` and code `{}`",
+                port,
+                icmp_type.0,
+                icmp_code.0
+            );
+
+            if icmp_type == IcmpTypes::DestinationUnreachable
+                && ICMP_TYPE_3_CODES.contains(&icmp_code)
+            {
+                return (PortState::Filtered, Reason::IcmpUnreach);
+            }
 
-        let interface = &interface::DEFAULT;
+            (
+                PortState::Closed,
+                icmp_reason(icmp_type == IcmpTypes::TimeExceeded),
+            )
+        }
+        Reply::Icmpv6(icmpv6_type, icmpv6_code) => {
+            log::debug!(
+                "Received ICMPv6 packet from port `{}` with type `{}` and code `{}`",
+                port,
+                icmpv6_type.0,
+                icmpv6_code.0
+            );
+
+            if icmpv6_type == Icmpv6Types::DestinationUnreachable
+                && ICMPV6_TYPE_1_CODES.contains(&icmpv6_code)
+            {
+                return (PortState::Filtered, Reason::IcmpUnreach);
+            }
 
-        let source_ip = interface.ip();
-        let source_port = rand::random();
+            (
+                PortState::Closed,
+                icmp_reason(icmpv6_type == Icmpv6Types::TimeExceeded),
+            )
+        }
+        _ => unreachable!(),
+    }
+}
 
-        let destination_ip = *addr.ip();
-        let destination_port = addr.port();
+/// Returns the running engine and the address that probes to `dest` are
+/// sent from.
+pub(super) fn source(dest: &IpAddr) -> Result<(&'static Engine, IpAddr), ScanError> {
+    Ok((engine::get()?, interface::default()?.ip_for(dest)?))
+}
 
-        // -> TCP packet.
-        let mut raw_tcp_pckt = [0; TCP_PKT_SZ];
-        let mut tcp_pckt = MutableTcpPacket::new(&mut raw_tcp_pckt).unwrap();
-        tcp_pckt.set_source(source_port);
-        tcp_pckt.set_destination(destination_port);
+/// Builds the TCP segment with `flags` sent to `addr`, wrapped up to the
+/// Ethernet layer. Only SYN segments carry options, as a connection would.
+pub(super) fn build_tcp(
+    source_ip: IpAddr,
+    addr: &SocketAddr,
+    source_port: u16,
+    sequence: u32,
+    flags: u8,
+) -> Result<Option<EthernetPacket<'static>>, ScanError> {
+    let destination_ip = addr.ip();
+    let syn = flags & TcpFlags::SYN != 0;
+
+    // -> TCP packet.
+    let mut raw_tcp_pckt = [0; TCP_PKT_SZ];
+    let tcp_pckt_sz = match syn {
+        true => TCP_PKT_SZ,
+        false => TCP_BARE_HDR_WORDS as usize * 4,
+    };
+    let mut tcp_pckt = MutableTcpPacket::new(&mut raw_tcp_pckt[..tcp_pckt_sz]).unwrap();
+    tcp_pckt.set_source(source_port);
+    tcp_pckt.set_destination(addr.port());
+    tcp_pckt.set_sequence(sequence);
+    tcp_pckt.set_flags(flags);
+    tcp_pckt.set_window(u16::MAX);
+    if syn {
         tcp_pckt.set_data_offset(TCP_HDR_WORDS);
-        tcp_pckt.set_flags(TcpFlags::SYN);
-        tcp_pckt.set_window(u16::MAX);
         tcp_pckt.set_options(&[
             TcpOption::mss(1460),
             TcpOption::sack_perm(),
@@ -90,107 +165,92 @@ impl Executor for SynScan {
             TcpOption::nop(),
             TcpOption::wscale(7),
         ]);
-        tcp_pckt.set_checksum(ipv4_checksum(
-            &tcp_pckt.to_immutable(),
-            &source_ip,
-            &destination_ip,
-        ));
-
-        let ethernet_pckt = pckt::build(
-            source_ip,
-            destination_ip,
-            IpNextHeaderProtocols::Tcp,
-            tcp_pckt.packet(),
-        );
+    } else {
+        tcp_pckt.set_data_offset(TCP_BARE_HDR_WORDS);
+    }
+    tcp_pckt.set_checksum(match (source_ip, destination_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dest)) => ipv4_checksum(&tcp_pckt.to_immutable(), &src, &dest),
+        (IpAddr::V6(src), IpAddr::V6(dest)) => ipv6_checksum(&tcp_pckt.to_immutable(), &src, &dest),
+        _ => unreachable!(),
+    });
+
+    pckt::build(
+        source_ip,
+        destination_ip,
+        IpNextHeaderProtocols::Tcp,
+        tcp_pckt.packet(),
+    )
+}
+
+#[derive(Debug)]
+pub struct SynScan {
+    flags: u8,
+}
+
+impl SynScan {
+    /// Sends SYN segments.
+    pub fn syn() -> Self {
+        Self {
+            flags: TcpFlags::SYN,
+        }
+    }
+
+    /// Sends segments with `flags`, whose replies are read as if they were
+    /// SYN segments.
+    pub fn with_flags(flags: u8) -> Self {
+        Self { flags }
+    }
+}
+
+impl Executor for SynScan {
+    fn scan(&self, addr: &SocketAddr, options: &ScanOptions) -> Probe {
+        let (engine, source_ip) = match source(&addr.ip()) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
+        };
+
+        let destination_port = addr.port();
 
-        let mut trials = 0..SEND_ATTEMPTS;
+        let build = |source_port| build_tcp(source_ip, addr, source_port, 0, self.flags);
+        let retries = options.retries.unwrap_or(SEND_RETRIES);
 
         // The following algorithm is based on https://nmap.org/book/synscan.html
 
-        loop {
-            match sender.send_to(ethernet_pckt.packet(), None).unwrap() {
-                Ok(_) => log::debug!("Sent `SYN` TCP packet to port `{}`", destination_port),
-                Err(e) if e.kind() == ErrorKind::TimedOut => return PortState::Unknown,
-                Err(e) => abort(ScanError::PacketSendFailed(IpAddr::V4(destination_ip), e)),
-            };
+        let reply = engine.probe(
+            addr,
+            IpNextHeaderProtocols::Tcp,
+            retries,
+            options.timeout.unwrap_or(SEND_TIMOUT),
+            build,
+        );
 
-            let timeout = Instant::now();
-
-            'rcv_lp: loop {
-                match receiver.next() {
-                    Ok(raw) => 'ok_blk: {
-                        let ethernet_pckt = EthernetPacket::new(raw).unwrap();
-                        if ethernet_pckt.get_ethertype() != EtherTypes::Ipv4 {
-                            break 'ok_blk;
-                        }
-
-                        let ipv4_pckt = Ipv4Packet::new(ethernet_pckt.payload()).unwrap();
-                        if !(ipv4_pckt.get_destination() == source_ip
-                            && ipv4_pckt.get_source() == destination_ip)
-                        {
-                            break 'ok_blk;
-                        }
-
-                        match ipv4_pckt.get_next_level_protocol() {
-                            IpNextHeaderProtocols::Tcp => {
-                                let tcp_pckt = TcpPacket::new(ipv4_pckt.payload()).unwrap();
-                                if !(tcp_pckt.get_destination() == source_port
-                                    && tcp_pckt.get_source() == destination_port)
-                                {
-                                    break 'ok_blk;
-                                }
-
-                                let tcp_flags = TcpKnownFlags(tcp_pckt.get_flags());
-
-                                log::debug!(
-                                    "Received `{}` TCP packet from port `{}`",
-                                    tcp_flags,
-                                    destination_port,
-                                );
-
-                                if tcp_flags.syn_ack() {
-                                    return PortState::Open;
-                                }
-
-                                // RST flag means closed and everyone else.
-                            }
-                            IpNextHeaderProtocols::Icmp => {
-                                let icmp_pckt = IcmpPacket::new(ipv4_pckt.payload()).unwrap();
-                                let icmp_type = icmp_pckt.get_icmp_type();
-                                let icmp_code = icmp_pckt.get_icmp_code();
-
-                                log::debug!(
-                                    "Received ICMP packet from port `{}` with type `{}` and code `{}`",
-                                    destination_port,
-                                    icmp_type.0,
-                                    icmp_code.0
-                                );
-
-                                if icmp_type == IcmpTypes::DestinationUnreachable
-                                    && ICMP_TYPE_3_CODES.contains(&icmp_code)
-                                {
-                                    return PortState::Filtered;
-                                }
-                            }
-                            _ => (), // Assumes that's closed.
-                        }
-
-                        return PortState::_Closed; // Gives up.
-                    }
-                    Err(e) if e.kind() == ErrorKind::TimedOut => (),
-                    Err(e) => abort(ScanError::PacketRecvFailed(IpAddr::V4(destination_ip), e)),
-                }
+        Probe::Waiting(Box::new(move || {
+            let reply = reply.recv().unwrap_or(Reply::Unsent);
+            let verdict = match reply {
+                Reply::Tcp { flags, .. } => {
+                    let tcp_flags = TcpKnownFlags(flags);
 
-                if timeout.elapsed() <= SEND_TIMOUT {
-                    continue;
-                }
+                    log::debug!(
+                        "Received `{}` TCP packet from port `{}`",
+                        tcp_flags,
+                        destination_port,
+                    );
 
-                if trials.next().is_some() {
-                    break 'rcv_lp; // Tries to resend SYN packet.
+                    if tcp_flags.syn_ack() {
+                        return Ok((PortState::Open, Reason::SynAck));
+                    }
+
+                    // RST flag means closed and everyone else.
+                    (PortState::Closed, Reason::Rst)
                 }
+                Reply::Icmp(..) | Reply::Icmpv6(..) => icmp_verdict(&reply, destination_port),
+                Reply::Udp | Reply::Neighbor(_) => unreachable!(),
+                Reply::Timeout => (PortState::Filtered, Reason::NoResponse(retries)),
+                Reply::Unsent => (PortState::Unknown, Reason::Unsent),
+                Reply::Failed(e) => return Err(e),
+            };
 
-                return PortState::Filtered;
-            }
-        }
+            Ok(verdict)
+        }))
     }
 }
@@ -1,18 +1,83 @@
 use std::{
-    net::{SocketAddr, SocketAddrV4, TcpStream},
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpStream},
+    sync::Mutex,
     time::Duration,
 };
 
-use crate::scan::{Executor, PortState};
+#[cfg(feature = "async")]
+use crate::scan::BannerGrab;
+use crate::scan::{banner, rate::LIMITER, Executor, PortState, Probe, Reason, ScanOptions};
 
-const TIMEOUT: Duration = Duration::from_millis(1500);
+pub(in crate::scan) const TIMEOUT: Duration = Duration::from_millis(1500);
 
-#[derive(Debug)]
-pub struct TcpScan;
+#[derive(Debug, Default)]
+pub struct TcpScan {
+    /// Banners read from open ports until the scanner takes them.
+    banners: Mutex<HashMap<SocketAddr, Vec<u8>>>,
+}
 
 impl Executor for TcpScan {
-    fn scan(&self, addr: &SocketAddrV4) -> PortState {
-        TcpStream::connect_timeout(&SocketAddr::V4(*addr), TIMEOUT)
-            .map_or(PortState::_Closed, |_| PortState::Open)
+    fn scan(&self, addr: &SocketAddr, options: &ScanOptions) -> Probe {
+        LIMITER.acquire();
+
+        let connected = TcpStream::connect_timeout(addr, options.timeout.unwrap_or(TIMEOUT));
+        if let (Ok(stream), Some(grab)) = (&connected, &options.banner) {
+            let banner = banner::read(stream, grab);
+            if !banner.is_empty() {
+                self.banners.lock().unwrap().insert(*addr, banner);
+            }
+        }
+
+        verdict(connected.map(drop)).into()
     }
+
+    fn banner(&self, addr: &SocketAddr) -> Option<Vec<u8>> {
+        self.banners.lock().unwrap().remove(addr)
+    }
+}
+
+/// Tells the state of a port from the outcome of connecting to it.
+fn verdict(connected: io::Result<()>) -> (PortState, Reason) {
+    // Retries are left to the kernel, so none are reported.
+    let reason = match connected {
+        Ok(_) => return (PortState::Open, Reason::SynAck),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Reason::ConnRefused,
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable
+            ) =>
+        {
+            Reason::IcmpUnreach
+        }
+        Err(_) => Reason::NoResponse(0),
+    };
+
+    (PortState::Closed, reason)
+}
+
+/// Connects to `addr` like [`TcpScan`] without blocking the thread, and
+/// reads its banner if `grab` is set.
+#[cfg(feature = "async")]
+pub(in crate::scan) async fn connect(
+    addr: SocketAddr,
+    timeout: Duration,
+    grab: Option<&BannerGrab>,
+) -> ((PortState, Reason), Option<Vec<u8>>) {
+    LIMITER.acquire_async().await;
+
+    let mut stream = match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return (verdict(Err(e)), None),
+        Err(_) => return (verdict(Err(ErrorKind::TimedOut.into())), None),
+    };
+
+    let banner = match grab {
+        Some(grab) => Some(banner::read_async(&mut stream, grab).await),
+        None => None,
+    };
+    (verdict(Ok(())), banner.filter(|b| !b.is_empty()))
 }
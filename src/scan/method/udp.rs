@@ -0,0 +1,199 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use pnet::packet::{
+    icmp::{destination_unreachable::IcmpCodes, IcmpTypes},
+    icmpv6::{Icmpv6Code, Icmpv6Types},
+    ip::IpNextHeaderProtocols,
+    udp::{ipv4_checksum, ipv6_checksum, MutableUdpPacket},
+    Packet,
+};
+
+use crate::scan::{engine::Reply, pckt, Executor, PortState, Probe, Reason, ScanOptions};
+
+use super::syn::source;
+
+const SEND_RETRIES: usize = 2;
+const SEND_TIMOUT: Duration = Duration::from_millis(2000);
+
+const UDP_HDR_SZ: usize = 8;
+
+const ICMPV6_PORT_UNREACHABLE: Icmpv6Code = Icmpv6Code(4);
+
+// Payloads are based on https://nmap.org/book/scan-methods-udp-scan.html and
+// the nmap-payloads file, since most services ignore empty datagrams.
+
+// DNS `version.bind` TXT query (CHAOS class).
+const DNS_PAYLOAD: &[u8] = b"\x00\x06\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+    \x07version\x04bind\x00\x00\x10\x00\x03";
+
+// NTPv3 client request.
+const NTP_PAYLOAD: &[u8] = b"\xe3\x00\x04\xfa\x00\x01\x00\x00\x00\x01\x00\x00\
+    \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+    \x00\x00\x00\x00\x00\x00\x00\x00\xc5\x4f\x23\x4b\x71\xb1\x52\xf3";
+
+// NetBIOS NBSTAT query for the wildcard name.
+const NETBIOS_PAYLOAD: &[u8] = b"\x80\xf0\x00\x10\x00\x01\x00\x00\x00\x00\x00\x00\
+    \x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00\x00\x21\x00\x01";
+
+// SNMPv1 get-request of `sysDescr.0` with community `public`.
+const SNMP_PAYLOAD: &[u8] = b"\x30\x29\x02\x01\x00\x04\x06public\xa0\x1c\
+    \x02\x04\x71\x64\xfe\xf1\x02\x01\x00\x02\x01\x00\x30\x0e\x30\x0c\
+    \x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x05\x00";
+
+// SSDP discovery request.
+const SSDP_PAYLOAD: &[u8] = b"M-SEARCH * HTTP/1.1\r\n\
+    HOST: 239.255.255.250:1900\r\n\
+    MAN: \"ssdp:discover\"\r\n\
+    MX: 1\r\n\
+    ST: ssdp:all\r\n\r\n";
+
+// mDNS query for `_services._dns-sd._udp.local` PTR records.
+const MDNS_PAYLOAD: &[u8] = b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+    \x09_services\x07_dns-sd\x04_udp\x05local\x00\x00\x0c\x00\x01";
+
+// Memcached `stats` command with UDP frame header.
+const MEMCACHED_PAYLOAD: &[u8] = b"\x00\x01\x00\x00\x00\x01\x00\x00stats\r\n";
+
+fn payload(port: u16) -> &'static [u8] {
+    match port {
+        53 => DNS_PAYLOAD,
+        123 => NTP_PAYLOAD,
+        137 => NETBIOS_PAYLOAD,
+        161 => SNMP_PAYLOAD,
+        1900 => SSDP_PAYLOAD,
+        5353 => MDNS_PAYLOAD,
+        11211 => MEMCACHED_PAYLOAD,
+        _ => &[],
+    }
+}
+
+#[derive(Debug)]
+pub struct UdpScan;
+
+impl Executor for UdpScan {
+    fn scan(&self, addr: &SocketAddr, options: &ScanOptions) -> Probe {
+        let (engine, source_ip) = match source(&addr.ip()) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
+        };
+
+        let destination_ip = addr.ip();
+        let destination_port = addr.port();
+
+        let payload = payload(destination_port);
+
+        // Prepare UDP packet.
+        let build = |source_port| {
+            // -> UDP packet.
+            let udp_pckt_sz = UDP_HDR_SZ + payload.len();
+            let mut raw_udp_pckt = vec![0; udp_pckt_sz];
+            let mut udp_pckt = MutableUdpPacket::new(&mut raw_udp_pckt).unwrap();
+            udp_pckt.set_source(source_port);
+            udp_pckt.set_destination(destination_port);
+            udp_pckt.set_length(udp_pckt_sz as u16);
+            udp_pckt.set_payload(payload);
+            udp_pckt.set_checksum(match (source_ip, destination_ip) {
+                (IpAddr::V4(src), IpAddr::V4(dest)) => {
+                    ipv4_checksum(&udp_pckt.to_immutable(), &src, &dest)
+                }
+                (IpAddr::V6(src), IpAddr::V6(dest)) => {
+                    ipv6_checksum(&udp_pckt.to_immutable(), &src, &dest)
+                }
+                _ => unreachable!(),
+            });
+
+            pckt::build(
+                source_ip,
+                destination_ip,
+                IpNextHeaderProtocols::Udp,
+                udp_pckt.packet(),
+            )
+        };
+
+        // The following algorithm is based on https://nmap.org/book/scan-methods-udp-scan.html
+
+        let retries = options.retries.unwrap_or(SEND_RETRIES);
+
+        let reply = engine.probe(
+            addr,
+            IpNextHeaderProtocols::Udp,
+            retries,
+            options.timeout.unwrap_or(SEND_TIMOUT),
+            build,
+        );
+
+        Probe::Waiting(Box::new(move || {
+            let verdict = match reply.recv().unwrap_or(Reply::Unsent) {
+                Reply::Udp => {
+                    log::debug!("Received UDP packet from port `{}`", destination_port);
+
+                    (PortState::Open, Reason::UdpResponse)
+                }
+                Reply::Icmp(icmp_type, icmp_code) => {
+                    log::debug!(
+                        "Received ICMP packet from port `{}` with type `{}` and code `{}`",
+                        destination_port,
+                        icmp_type.0,
+                        icmp_code.0
+                    );
+
+                    if icmp_type != IcmpTypes::DestinationUnreachable {
+                        // Expired on the way.
+                        return Ok((PortState::OpenFiltered, Reason::TimeExceeded));
+                    }
+
+                    if icmp_code == IcmpCodes::DestinationPortUnreachable {
+                        return Ok((PortState::Closed, Reason::IcmpPortUnreach));
+                    }
+
+                    (PortState::Filtered, Reason::IcmpUnreach)
+                }
+                Reply::Icmpv6(icmpv6_type, icmpv6_code) => {
+                    log::debug!(
+                        "Received ICMPv6 packet from port `{}` with type `{}` and code `{}`",
+                        destination_port,
+                        icmpv6_type.0,
+                        icmpv6_code.0
+                    );
+
+                    if icmpv6_type != Icmpv6Types::DestinationUnreachable {
+                        return Ok((PortState::OpenFiltered, Reason::TimeExceeded));
+                    }
+
+                    if icmpv6_code == ICMPV6_PORT_UNREACHABLE {
+                        return Ok((PortState::Closed, Reason::IcmpPortUnreach));
+                    }
+
+                    (PortState::Filtered, Reason::IcmpUnreach)
+                }
+                Reply::Tcp { .. } | Reply::Neighbor(_) => unreachable!(),
+                Reply::Timeout => (PortState::OpenFiltered, Reason::NoResponse(retries)),
+                // Host isn't reachable.
+                Reply::Unsent => (PortState::Unknown, Reason::Unsent),
+                Reply::Failed(e) => return Err(e),
+            };
+
+            Ok(verdict)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_per_port() {
+        assert_eq!(payload(53), DNS_PAYLOAD);
+        assert_eq!(payload(161), SNMP_PAYLOAD);
+        assert!(payload(9999).is_empty());
+    }
+
+    #[test]
+    fn snmp_payload_length_matches_encoding() {
+        assert_eq!(SNMP_PAYLOAD.len(), SNMP_PAYLOAD[1] as usize + 2);
+    }
+}
@@ -0,0 +1,297 @@
+//! Next-hop MAC address resolution for IPv6 through the Neighbor Discovery
+//! Protocol (RFC 4861), which replaces ARP.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::Duration,
+};
+
+use once_cell::sync::{Lazy, OnceCell};
+use pnet::{
+    packet::{
+        ethernet::EtherTypes,
+        icmpv6::{
+            self,
+            ndp::{
+                MutableNeighborSolicitPacket, MutableRouterSolicitPacket, NdpOption,
+                NdpOptionTypes, NeighborAdvertPacket, RouterAdvertPacket,
+            },
+            Icmpv6Packet, Icmpv6Type, Icmpv6Types,
+        },
+        ip::IpNextHeaderProtocols,
+        Packet,
+    },
+    util::MacAddr,
+};
+
+use crate::{
+    error::ScanError,
+    scan::{
+        engine::{self, Reply},
+        interface, pckt,
+    },
+};
+
+const SOLICIT_RETRIES: usize = 2;
+const SOLICIT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+// NDP messages are dropped by the receiver if they have been forwarded.
+const NDP_HOP_LIMIT: u8 = 255;
+
+const NS_PKT_SZ: usize = 32;
+const RS_PKT_SZ: usize = 16;
+
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+type Neighbor = Arc<OnceCell<Option<MacAddr>>>;
+
+// Neighbors that didn't answer are kept as well, so they're solicited only
+// once, even by concurrent probes.
+static NEIGHBORS: Lazy<Mutex<HashMap<Ipv6Addr, Neighbor>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static ROUTER: OnceCell<Option<MacAddr>> = OnceCell::new();
+
+/// Returns the MAC address of the host that must receive packets sent to
+/// `dest`, or nothing if it can't be reached.
+pub fn next_hop(dest: Ipv6Addr) -> Result<Option<MacAddr>, ScanError> {
+    if !interface::default()?.is_on_link(dest) {
+        return ROUTER.get_or_try_init(router).copied();
+    }
+
+    neighbor(dest)
+        .get_or_try_init(|| Ok(found(dest, learned(neighbor_solicit(dest)?)?)))
+        .copied()
+}
+
+/// Solicits every on-link neighbor in `dests` at once, instead of one after
+/// the other as their next hops are looked up.
+pub fn resolve(dests: &[IpAddr]) -> Result<(), ScanError> {
+    let interface = interface::default()?;
+
+    let solicited = dests
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V6(ip) if interface.is_on_link(*ip) => Some(*ip),
+            _ => None,
+        })
+        .map(|dest| (dest, neighbor(dest)))
+        .filter(|(_, cell)| cell.get().is_none())
+        .map(|(dest, cell)| Ok((dest, cell, neighbor_solicit(dest)?)))
+        .collect::<Result<Vec<_>, ScanError>>()?;
+
+    solicited.into_iter().try_for_each(|(dest, cell, reply)| {
+        cell.get_or_try_init(|| Ok(found(dest, learned(reply)?)))
+            .map(|_| ())
+    })
+}
+
+/// Returns the address and the kind of solicitation that the NDP message
+/// answers, if it is a usable advertisement.
+pub fn advertised(icmpv6_pckt: &[u8]) -> Option<(Ipv6Addr, Icmpv6Type)> {
+    match Icmpv6Packet::new(icmpv6_pckt)?.get_icmpv6_type() {
+        Icmpv6Types::NeighborAdvert => Some((
+            NeighborAdvertPacket::new(icmpv6_pckt)?.get_target_addr(),
+            Icmpv6Types::NeighborSolicit,
+        )),
+        Icmpv6Types::RouterAdvert if RouterAdvertPacket::new(icmpv6_pckt)?.get_lifetime() > 0 => {
+            Some((ALL_ROUTERS, Icmpv6Types::RouterSolicit))
+        }
+        _ => None,
+    }
+}
+
+fn router() -> Result<Option<MacAddr>, ScanError> {
+    let mac = learned(router_solicit()?)?;
+
+    match mac {
+        Some(mac) => log::debug!("Found IPv6 router MAC address `{}`", mac),
+        None => log::debug!("No IPv6 router answered the router solicitation"),
+    }
+
+    Ok(mac)
+}
+
+fn neighbor(dest: Ipv6Addr) -> Neighbor {
+    NEIGHBORS.lock().unwrap().entry(dest).or_default().clone()
+}
+
+fn found(dest: Ipv6Addr, mac: Option<MacAddr>) -> Option<MacAddr> {
+    match mac {
+        Some(mac) => log::debug!("Found MAC address `{}` of neighbor `{}`", mac, dest),
+        None => log::debug!(
+            "Neighbor `{}` didn't answer the neighbor solicitation",
+            dest
+        ),
+    }
+
+    mac
+}
+
+fn learned(reply: Receiver<Reply>) -> Result<Option<MacAddr>, ScanError> {
+    match reply.recv() {
+        Ok(Reply::Neighbor(mac)) => Ok(Some(mac)),
+        Ok(Reply::Failed(e)) => Err(e),
+        _ => Ok(None),
+    }
+}
+
+#[inline]
+fn multicast_mac(group: Ipv6Addr) -> MacAddr {
+    let octets = group.octets();
+    MacAddr::new(0x33, 0x33, octets[12], octets[13], octets[14], octets[15])
+}
+
+#[inline]
+fn source_ll_addr(mac: MacAddr) -> NdpOption {
+    NdpOption {
+        option_type: NdpOptionTypes::SourceLLAddr,
+        length: 1,
+        data: mac.octets().to_vec(),
+    }
+}
+
+fn neighbor_solicit(target: Ipv6Addr) -> Result<Receiver<Reply>, ScanError> {
+    let interface = interface::default()?;
+
+    let source_ip = interface.ipv6()?;
+    let [.., b13, b14, b15] = target.octets();
+    let destination_ip = Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | b13 as u16,
+        u16::from_be_bytes([b14, b15]),
+    );
+
+    let mut raw_ns_pckt = [0; NS_PKT_SZ];
+    let mut ns_pckt = MutableNeighborSolicitPacket::new(&mut raw_ns_pckt).unwrap();
+    ns_pckt.set_icmpv6_type(Icmpv6Types::NeighborSolicit);
+    ns_pckt.set_target_addr(target);
+    ns_pckt.set_options(&[source_ll_addr(interface.mac())]);
+    let checksum = icmpv6::checksum(
+        &Icmpv6Packet::new(ns_pckt.packet()).unwrap(),
+        &source_ip,
+        &destination_ip,
+    );
+    ns_pckt.set_checksum(checksum);
+
+    solicit(
+        source_ip,
+        destination_ip,
+        target,
+        Icmpv6Types::NeighborSolicit,
+        ns_pckt.packet(),
+    )
+}
+
+fn router_solicit() -> Result<Receiver<Reply>, ScanError> {
+    let interface = interface::default()?;
+
+    let source_ip = interface.ipv6()?;
+
+    let mut raw_rs_pckt = [0; RS_PKT_SZ];
+    let mut rs_pckt = MutableRouterSolicitPacket::new(&mut raw_rs_pckt).unwrap();
+    rs_pckt.set_icmpv6_type(Icmpv6Types::RouterSolicit);
+    rs_pckt.set_options(&[source_ll_addr(interface.mac())]);
+    let checksum = icmpv6::checksum(
+        &Icmpv6Packet::new(rs_pckt.packet()).unwrap(),
+        &source_ip,
+        &ALL_ROUTERS,
+    );
+    rs_pckt.set_checksum(checksum);
+
+    solicit(
+        source_ip,
+        ALL_ROUTERS,
+        ALL_ROUTERS,
+        Icmpv6Types::RouterSolicit,
+        rs_pckt.packet(),
+    )
+}
+
+/// Queues an NDP message through the probe engine, which hands over the
+/// MAC address of whoever answers it.
+fn solicit(
+    source_ip: Ipv6Addr,
+    destination_ip: Ipv6Addr,
+    target: Ipv6Addr,
+    kind: Icmpv6Type,
+    icmpv6_pckt: &[u8],
+) -> Result<Receiver<Reply>, ScanError> {
+    let ethernet_pckt = pckt::ethernet(
+        EtherTypes::Ipv6,
+        multicast_mac(destination_ip),
+        pckt::build_ipv6(
+            source_ip,
+            destination_ip,
+            IpNextHeaderProtocols::Icmpv6,
+            NDP_HOP_LIMIT,
+            icmpv6_pckt,
+        )
+        .packet(),
+    )?;
+
+    log::debug!("Sending NDP solicitation to `{}`", destination_ip);
+
+    Ok(engine::get()?.solicit(
+        target,
+        kind,
+        SOLICIT_RETRIES,
+        SOLICIT_TIMEOUT,
+        ethernet_pckt,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::icmpv6::ndp::{MutableNeighborAdvertPacket, MutableRouterAdvertPacket};
+
+    use super::*;
+
+    #[test]
+    fn neighbor_advert_answers_solicit() {
+        let target = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+
+        let mut raw = [0; 24];
+        let mut na_pckt = MutableNeighborAdvertPacket::new(&mut raw).unwrap();
+        na_pckt.set_icmpv6_type(Icmpv6Types::NeighborAdvert);
+        na_pckt.set_target_addr(target);
+
+        assert_eq!(
+            advertised(&raw),
+            Some((target, Icmpv6Types::NeighborSolicit))
+        );
+        assert_eq!(advertised(&raw[..20]), None);
+    }
+
+    #[test]
+    fn router_advert_needs_lifetime() {
+        let mut raw = [0; 16];
+        let mut ra_pckt = MutableRouterAdvertPacket::new(&mut raw).unwrap();
+        ra_pckt.set_icmpv6_type(Icmpv6Types::RouterAdvert);
+
+        assert_eq!(advertised(ra_pckt.packet()), None);
+
+        ra_pckt.set_lifetime(1800);
+
+        assert_eq!(
+            advertised(ra_pckt.packet()),
+            Some((ALL_ROUTERS, Icmpv6Types::RouterSolicit))
+        );
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        let mut raw = [0; 32];
+        raw[0] = Icmpv6Types::NeighborSolicit.0;
+
+        assert_eq!(advertised(&raw), None);
+        assert_eq!(advertised(&[]), None);
+    }
+}
@@ -1,30 +1,75 @@
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-use pnet::packet::{
-    ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
-    ip::IpNextHeaderProtocol,
-    ipv4::{self, Ipv4Flags, MutableIpv4Packet},
-    Packet,
+use pnet::{
+    packet::{
+        ethernet::{EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket},
+        ip::IpNextHeaderProtocol,
+        ipv4::{self, Ipv4Flags, Ipv4Packet, MutableIpv4Packet},
+        ipv6::{Ipv6Packet, MutableIpv6Packet},
+        Packet,
+    },
+    util::MacAddr,
 };
 
-use crate::scan::interface;
+use crate::{
+    error::ScanError,
+    scan::{interface, ndp},
+};
 
 const IPV4_HDR_SZ: u8 = 20;
 const IPV4_HDR_WORDS: u8 = IPV4_HDR_SZ / 4;
 const IPV4_TTL: u8 = 64;
 
+const IPV6_HDR_SZ: usize = 40;
+const IPV6_HOP_LIMIT: u8 = 64;
+
 const ETHERNET_HDR_SZ: usize = 14;
 
+/// Fields of an IP packet received through the datalink channel.
+pub struct IpPayload<'a> {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: IpNextHeaderProtocol,
+    pub payload: &'a [u8],
+}
+
+/// Wraps the transport segment into an Ethernet frame addressed to the
+/// next hop, or returns nothing if the next hop can't be resolved.
 pub fn build(
+    src: IpAddr,
+    dest: IpAddr,
+    next_level_proto: IpNextHeaderProtocol,
+    raw_packet: &[u8],
+) -> Result<Option<EthernetPacket<'static>>, ScanError> {
+    let ethernet_pckt = match (src, dest) {
+        (IpAddr::V4(src), IpAddr::V4(dest)) => ethernet(
+            EtherTypes::Ipv4,
+            interface::gateway()?,
+            build_ipv4(src, dest, next_level_proto, raw_packet).packet(),
+        )?,
+        (IpAddr::V6(src), IpAddr::V6(dest)) => {
+            let Some(next_hop) = ndp::next_hop(dest)? else {
+                return Ok(None);
+            };
+
+            ethernet(
+                EtherTypes::Ipv6,
+                next_hop,
+                build_ipv6(src, dest, next_level_proto, IPV6_HOP_LIMIT, raw_packet).packet(),
+            )?
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(Some(ethernet_pckt))
+}
+
+fn build_ipv4(
     src: Ipv4Addr,
     dest: Ipv4Addr,
     next_level_proto: IpNextHeaderProtocol,
     raw_packet: &[u8],
-) -> EthernetPacket {
-    let interface = &interface::DEFAULT;
-    let gateway = *interface::GATEWAY;
-
-    // -> IPv4 packet.
+) -> MutableIpv4Packet<'static> {
     let ipv4_pckt_sz = IPV4_HDR_SZ as usize + raw_packet.len();
     let raw_ipv4_pckt = vec![0; ipv4_pckt_sz];
     let mut ipv4_pckt = MutableIpv4Packet::owned(raw_ipv4_pckt).unwrap();
@@ -40,14 +85,167 @@ pub fn build(
     ipv4_pckt.set_checksum(ipv4::checksum(&ipv4_pckt.to_immutable()));
     ipv4_pckt.set_payload(raw_packet);
 
-    // -> Ethernet packet.
-    let ethernet_pckt_sz = ETHERNET_HDR_SZ + ipv4_pckt_sz;
+    ipv4_pckt
+}
+
+pub fn build_ipv6(
+    src: Ipv6Addr,
+    dest: Ipv6Addr,
+    next_level_proto: IpNextHeaderProtocol,
+    hop_limit: u8,
+    raw_packet: &[u8],
+) -> MutableIpv6Packet<'static> {
+    let ipv6_pckt_sz = IPV6_HDR_SZ + raw_packet.len();
+    let raw_ipv6_pckt = vec![0; ipv6_pckt_sz];
+    let mut ipv6_pckt = MutableIpv6Packet::owned(raw_ipv6_pckt).unwrap();
+    ipv6_pckt.set_version(6);
+    ipv6_pckt.set_payload_length(raw_packet.len() as u16);
+    ipv6_pckt.set_next_header(next_level_proto);
+    ipv6_pckt.set_hop_limit(hop_limit);
+    ipv6_pckt.set_source(src);
+    ipv6_pckt.set_destination(dest);
+    ipv6_pckt.set_payload(raw_packet);
+
+    ipv6_pckt
+}
+
+pub fn ethernet(
+    ethertype: EtherType,
+    dest: MacAddr,
+    raw_packet: &[u8],
+) -> Result<EthernetPacket<'static>, ScanError> {
+    let interface = interface::default()?;
+
+    let ethernet_pckt_sz = ETHERNET_HDR_SZ + raw_packet.len();
     let raw_ethernet_pckt = vec![0; ethernet_pckt_sz];
     let mut ethernet_pckt = MutableEthernetPacket::owned(raw_ethernet_pckt).unwrap();
-    ethernet_pckt.set_ethertype(EtherTypes::Ipv4);
+    ethernet_pckt.set_ethertype(ethertype);
     ethernet_pckt.set_source(interface.mac());
-    ethernet_pckt.set_destination(gateway);
-    ethernet_pckt.set_payload(ipv4_pckt.packet());
+    ethernet_pckt.set_destination(dest);
+    ethernet_pckt.set_payload(raw_packet);
+
+    Ok(ethernet_pckt.consume_to_immutable())
+}
+
+/// Extracts the IP layer of a received Ethernet frame, if it carries one.
+pub fn unwrap(raw: &[u8]) -> Option<IpPayload<'_>> {
+    let ethernet_pckt = EthernetPacket::new(raw)?;
+    let raw_ip_pckt = raw.get(ETHERNET_HDR_SZ..)?;
+
+    match ethernet_pckt.get_ethertype() {
+        EtherTypes::Ipv4 => {
+            let ipv4_pckt = Ipv4Packet::new(raw_ip_pckt)?;
+            let start = ipv4_pckt.get_header_length() as usize * 4;
+            let end = (ipv4_pckt.get_total_length() as usize).min(raw_ip_pckt.len());
+
+            Some(IpPayload {
+                source: IpAddr::V4(ipv4_pckt.get_source()),
+                destination: IpAddr::V4(ipv4_pckt.get_destination()),
+                protocol: ipv4_pckt.get_next_level_protocol(),
+                payload: raw_ip_pckt.get(start..end)?,
+            })
+        }
+        EtherTypes::Ipv6 => {
+            let ipv6_pckt = Ipv6Packet::new(raw_ip_pckt)?;
+            let end =
+                (IPV6_HDR_SZ + ipv6_pckt.get_payload_length() as usize).min(raw_ip_pckt.len());
+
+            Some(IpPayload {
+                source: IpAddr::V6(ipv6_pckt.get_source()),
+                destination: IpAddr::V6(ipv6_pckt.get_destination()),
+                protocol: ipv6_pckt.get_next_header(),
+                payload: raw_ip_pckt.get(IPV6_HDR_SZ..end)?,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Extracts the destination address, the transport protocol and the source
+/// and destination ports of the segment quoted by an ICMP error message.
+pub fn quoted(raw: &[u8]) -> Option<(IpAddr, IpNextHeaderProtocol, u16, u16)> {
+    let (destination, protocol, offset) = match raw.first()? >> 4 {
+        4 => {
+            let ipv4_pckt = Ipv4Packet::new(raw)?;
+            (
+                IpAddr::V4(ipv4_pckt.get_destination()),
+                ipv4_pckt.get_next_level_protocol(),
+                ipv4_pckt.get_header_length() as usize * 4,
+            )
+        }
+        6 => {
+            let ipv6_pckt = Ipv6Packet::new(raw)?;
+            (
+                IpAddr::V6(ipv6_pckt.get_destination()),
+                ipv6_pckt.get_next_header(),
+                IPV6_HDR_SZ,
+            )
+        }
+        _ => return None,
+    };
+
+    let ports = raw.get(offset..offset + 4)?;
+
+    Some((
+        destination,
+        protocol,
+        u16::from_be_bytes([ports[0], ports[1]]),
+        u16::from_be_bytes([ports[2], ports[3]]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    use super::*;
+
+    fn quoted_ipv4(destination: Ipv4Addr, source_port: u16, destination_port: u16) -> Vec<u8> {
+        let mut raw = vec![0; 28];
+        raw[0] = 0x45; // Version 4 with 5 words of header.
+        raw[9] = IpNextHeaderProtocols::Udp.0;
+        raw[16..20].copy_from_slice(&destination.octets());
+        raw[20..22].copy_from_slice(&source_port.to_be_bytes());
+        raw[22..24].copy_from_slice(&destination_port.to_be_bytes());
+        raw
+    }
+
+    fn quoted_ipv6(destination: Ipv6Addr, source_port: u16, destination_port: u16) -> Vec<u8> {
+        let mut raw = vec![0; 48];
+        raw[0] = 0x60;
+        raw[6] = IpNextHeaderProtocols::Tcp.0;
+        raw[24..40].copy_from_slice(&destination.octets());
+        raw[40..42].copy_from_slice(&source_port.to_be_bytes());
+        raw[42..44].copy_from_slice(&destination_port.to_be_bytes());
+        raw
+    }
+
+    #[test]
+    fn quoted_ipv4_segment() {
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        assert_eq!(
+            quoted(&quoted_ipv4(ip, 4000, 53)),
+            Some((IpAddr::V4(ip), IpNextHeaderProtocols::Udp, 4000, 53))
+        );
+    }
+
+    #[test]
+    fn quoted_ipv6_segment() {
+        let ip = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+
+        assert_eq!(
+            quoted(&quoted_ipv6(ip, 4000, 443)),
+            Some((IpAddr::V6(ip), IpNextHeaderProtocols::Tcp, 4000, 443))
+        );
+    }
+
+    #[test]
+    fn truncated_quote() {
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
 
-    ethernet_pckt.consume_to_immutable()
+        assert_eq!(quoted(&quoted_ipv4(ip, 4000, 53)[..22]), None);
+        assert_eq!(quoted(&[0x75]), None);
+        assert_eq!(quoted(&[]), None);
+    }
 }
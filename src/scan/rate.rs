@@ -0,0 +1,182 @@
+//! Pacing of the probes sent by every technique.
+
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+// Tokens that can be saved up, as a fraction of the rate.
+const BURST_SECS: f64 = 0.1;
+
+// Bounds of the congestion window, in probes.
+const MIN_WINDOW: f64 = 1.0;
+const INITIAL_WINDOW: f64 = 10.0;
+const MAX_WINDOW: f64 = 10000.0;
+
+pub static LIMITER: Lazy<RateLimiter> = Lazy::new(RateLimiter::default);
+
+/// Caps the packets per second sent by the whole scan, which isn't limited
+/// until configured.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    bucket: Mutex<Option<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn configure(&self, rate: Option<u32>) {
+        *self.bucket.lock().unwrap() = rate.map(TokenBucket::new);
+    }
+
+    /// Blocks until another packet can be sent.
+    pub fn acquire(&self) {
+        while let Some(wait) = self.wait() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Waits like [`RateLimiter::acquire`] without blocking the thread.
+    #[cfg(feature = "async")]
+    pub async fn acquire_async(&self) {
+        while let Some(wait) = self.wait() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Takes a token if there's any, or returns how long until there is.
+    fn wait(&self) -> Option<Duration> {
+        self.bucket
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|bucket| bucket.take(Instant::now()))
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> Self {
+        let rate = rate as f64;
+        let capacity = (rate * BURST_SECS).max(1.0);
+
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available.
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        let refill = now.saturating_duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+}
+
+/// Number of probes allowed in flight, which grows with every reply and is
+/// halved when probes are dropped, as TCP's congestion control.
+#[derive(Debug, Clone, Copy)]
+pub struct CongestionWindow {
+    size: f64,
+    threshold: f64,
+}
+
+impl Default for CongestionWindow {
+    fn default() -> Self {
+        Self {
+            size: INITIAL_WINDOW,
+            threshold: MAX_WINDOW,
+        }
+    }
+}
+
+impl CongestionWindow {
+    pub fn on_reply(&mut self) {
+        // Exponential growth until the first drop, linear afterwards.
+        let growth = match self.size < self.threshold {
+            true => 1.0,
+            false => 1.0 / self.size,
+        };
+
+        self.size = (self.size + growth).min(MAX_WINDOW);
+    }
+
+    pub fn on_drop(&mut self) {
+        self.threshold = (self.size / 2.0).max(MIN_WINDOW);
+        self.size = self.threshold;
+    }
+
+    #[inline]
+    pub fn admits(&self, in_flight: usize) -> bool {
+        (in_flight as f64) < self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_burst_then_waits() {
+        let mut bucket = TokenBucket::new(100);
+        let now = bucket.refilled;
+
+        (0..10).for_each(|_| assert_eq!(bucket.take(now), None));
+
+        assert_eq!(bucket.take(now), Some(Duration::from_millis(10)));
+        assert_eq!(bucket.take(now + Duration::from_millis(10)), None);
+    }
+
+    #[test]
+    fn bucket_doesnt_save_up_past_capacity() {
+        let mut bucket = TokenBucket::new(100);
+        let later = bucket.refilled + Duration::from_secs(60);
+
+        (0..10).for_each(|_| assert_eq!(bucket.take(later), None));
+
+        assert!(bucket.take(later).is_some());
+    }
+
+    #[test]
+    fn window_halves_on_drop() {
+        let mut window = CongestionWindow::default();
+        (0..10).for_each(|_| window.on_reply());
+
+        assert!(window.admits(19) && !window.admits(20));
+
+        window.on_drop();
+
+        assert!(window.admits(9) && !window.admits(10));
+
+        // Linear growth once a drop was seen.
+        (0..10).for_each(|_| window.on_reply());
+
+        assert!(!window.admits(11));
+    }
+
+    #[test]
+    fn window_keeps_one_probe() {
+        let mut window = CongestionWindow::default();
+        (0..10).for_each(|_| window.on_drop());
+
+        assert!(window.admits(0) && !window.admits(1));
+    }
+}
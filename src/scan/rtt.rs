@@ -0,0 +1,98 @@
+//! Retransmission timeouts derived from the round-trip times measured for
+//! each host, following the TCP algorithm of RFC 6298.
+
+use std::time::Duration;
+
+// Lower bound of the timeout, as nmap's default `--min-rtt-timeout`.
+const MIN_RTO: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    backoff: u32,
+}
+
+impl RttEstimator {
+    /// Updates the estimates with the round-trip time of a probe that was
+    /// sent only once, as retransmitted ones are ambiguous (Karn's
+    /// algorithm).
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+
+        self.backoff = 0;
+    }
+
+    /// Doubles the timeout after a probe got no reply.
+    pub fn back_off(&mut self) {
+        self.backoff = (self.backoff + 1).min(16);
+    }
+
+    /// Returns the retransmission timeout, capped at `max`, which is also
+    /// used before any round-trip time is known.
+    pub fn timeout(&self, max: Duration) -> Duration {
+        let Some(srtt) = self.srtt else {
+            return max;
+        };
+
+        let rto = (srtt + self.rttvar * 4).max(MIN_RTO);
+
+        rto.saturating_mul(1 << self.backoff).min(max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: Duration = Duration::from_secs(4);
+
+    #[test]
+    fn unknown_host_waits_longest() {
+        assert_eq!(RttEstimator::default().timeout(MAX), MAX);
+    }
+
+    #[test]
+    fn first_sample() {
+        let mut rtt = RttEstimator::default();
+        rtt.sample(Duration::from_millis(200));
+
+        // 200 ms + 4 * 100 ms.
+        assert_eq!(rtt.timeout(MAX), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn stable_samples_converge() {
+        let mut rtt = RttEstimator::default();
+        (0..50).for_each(|_| rtt.sample(Duration::from_millis(10)));
+
+        assert_eq!(rtt.timeout(MAX), MIN_RTO);
+    }
+
+    #[test]
+    fn back_off_doubles_until_sampled() {
+        let mut rtt = RttEstimator::default();
+        rtt.sample(Duration::from_millis(200));
+        rtt.back_off();
+
+        assert_eq!(rtt.timeout(MAX), Duration::from_millis(1200));
+
+        rtt.back_off();
+        rtt.back_off();
+
+        assert_eq!(rtt.timeout(MAX), MAX);
+
+        rtt.sample(Duration::from_millis(200));
+
+        assert!(rtt.timeout(MAX) < Duration::from_millis(1200));
+    }
+}
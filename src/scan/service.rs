@@ -0,0 +1,242 @@
+//! Service and version detection, based on
+//! https://nmap.org/book/vscan-technique.html: open TCP ports are sent the
+//! probes of a database until a response matches what some service sends.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+
+use self::probes::{Match, ServiceProbe};
+
+use super::{HostResult, PortState, Protocol};
+
+mod probes;
+
+// Time to wait for a connection, unless the scan sets a timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(1500);
+
+// Most bytes of a response that are matched.
+const MAX_RESPONSE: usize = 16 * 1024;
+
+static PROBES: Lazy<Vec<ServiceProbe>> = Lazy::new(|| {
+    probes::parse(include_str!("service/pscan-service-probes"))
+        .expect("built-in service probes are valid")
+});
+
+/// What listens on a port, as far as its responses tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Service {
+    /// Name of the protocol (e.g. `ssh` or `http`).
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Anything else about the product (e.g. the protocol version).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+impl Service {
+    /// Product, version and info joined as nmap shows them (e.g.
+    /// `OpenSSH 9.6p1 (protocol 2.0)`), which might be empty.
+    pub fn description(&self) -> String {
+        let mut description = [&self.product, &self.version]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(info) = &self.info {
+            if !description.is_empty() {
+                description.push(' ');
+            }
+            description.push_str(&format!("({})", info));
+        }
+
+        description
+    }
+}
+
+/// Identifies the service of every open TCP port of `hosts`.
+pub fn detect(hosts: &mut [HostResult], timeout: Option<Duration>) {
+    let timeout = timeout.unwrap_or(CONNECT_TIMEOUT);
+
+    hosts
+        .iter_mut()
+        .flat_map(|hr| {
+            let ip = hr.ip;
+            hr.ports
+                .iter_mut()
+                .filter(|pr| pr.state == PortState::Open && pr.protocol == Protocol::Tcp)
+                .map(move |pr| (ip, pr))
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .for_each(|(ip, pr)| {
+            pr.service = identify(&SocketAddr::new(ip, pr.port), timeout);
+            if let Some(service) = &pr.service {
+                log::debug!("Identified `{}` on port `{}`", service.name, pr.port);
+            }
+        });
+}
+
+/// Sends the probes meant for the port of `addr` first and then the rest,
+/// until a response matches. A service that was only told by a soft match
+/// is only sent the probes that can tell more about it.
+fn identify(addr: &SocketAddr, timeout: Duration) -> Option<Service> {
+    let null = PROBES.iter().find(|probe| probe.payload.is_empty());
+    let (targeted, rest) = PROBES
+        .iter()
+        .filter(|probe| !probe.payload.is_empty())
+        .partition::<Vec<_>, _>(|probe| probe.targets(addr.port()));
+
+    let mut soft: Option<Service> = None;
+    for probe in null.into_iter().chain(targeted).chain(rest) {
+        if let Some(soft) = &soft {
+            if !probe.matches.iter().any(|m| m.service == soft.name) {
+                continue;
+            }
+        }
+
+        // The NULL probe's matches apply to the banners sent before any
+        // response to the probe.
+        let matches = probe
+            .matches
+            .iter()
+            .chain(null.into_iter().flat_map(|null| &null.matches))
+            .filter(|m| soft.as_ref().is_none_or(|soft| m.service == soft.name))
+            .collect::<Vec<_>>();
+
+        log::debug!("Sending service probe `{}` to `{}`", probe.name, addr);
+
+        let response = match exchange(addr, probe, timeout, &matches) {
+            Ok(response) => response,
+            // Ports that stopped accepting connections won't tell more.
+            Err(_) => break,
+        };
+
+        for m in &matches {
+            match m.apply(&response) {
+                Some(service) if !m.soft => return Some(service),
+                Some(service) => {
+                    soft.get_or_insert(service);
+                }
+                None => (),
+            }
+        }
+    }
+
+    soft
+}
+
+/// Sends the payload of `probe` over a new connection to `addr` and reads
+/// the response until the wait of the probe is over, the connection is
+/// closed or a hard match succeeds.
+fn exchange(
+    addr: &SocketAddr,
+    probe: &ServiceProbe,
+    timeout: Duration,
+    matches: &[&Match],
+) -> std::io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(addr, timeout)?;
+    stream.write_all(&probe.payload)?;
+
+    let start = Instant::now();
+    let mut response = vec![];
+    let mut buf = [0; 4096];
+    while response.len() < MAX_RESPONSE {
+        let Some(left) = probe.wait.checked_sub(start.elapsed()) else {
+            break;
+        };
+        stream.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                response.extend(&buf[..n]);
+                if matches
+                    .iter()
+                    .any(|m| !m.soft && m.apply(&response).is_some())
+                {
+                    break;
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            // Resets after a response still leave it to be matched.
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::BufRead, net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn built_in_probes_are_valid() {
+        assert!(PROBES.iter().any(|probe| probe.name == "NULL"));
+        assert!(PROBES
+            .iter()
+            .all(|probe| probe.payload.is_empty() || !probe.ports.is_empty()));
+    }
+
+    #[test]
+    fn identify_banner_and_probed_services() {
+        let ssh = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ssh_addr = ssh.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = ssh.accept().unwrap();
+            stream
+                .write_all(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n")
+                .unwrap();
+        });
+
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_addr = http.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in http.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                std::io::BufReader::new(&stream)
+                    .read_line(&mut request)
+                    .unwrap();
+                if request.starts_with("GET / ") {
+                    stream
+                        .write_all(b"HTTP/1.0 200 OK\r\nServer: nginx/1.24.0\r\n\r\n")
+                        .unwrap();
+                    return;
+                }
+            }
+        });
+
+        let timeout = Duration::from_millis(500);
+        let service = identify(&ssh_addr, timeout).unwrap();
+        assert_eq!(service.name, "ssh");
+        assert_eq!(
+            service.description(),
+            "OpenSSH 9.6p1 Ubuntu 3ubuntu13.5 (Ubuntu Linux; protocol 2.0)"
+        );
+
+        let service = identify(&http_addr, timeout).unwrap();
+        assert_eq!(service.name, "http");
+        assert_eq!(service.description(), "nginx 1.24.0");
+    }
+}
@@ -0,0 +1,278 @@
+//! Parser of the probe database, a subset of nmap's `nmap-service-probes`
+//! format described in the database itself.
+
+use std::{ops::RangeInclusive, time::Duration};
+
+use regex::bytes::{Captures, Regex, RegexBuilder};
+
+use super::Service;
+
+// Time to wait for a response if the probe doesn't say.
+const DEFAULT_WAIT: Duration = Duration::from_millis(5000);
+
+#[derive(Debug)]
+pub struct ServiceProbe {
+    pub name: String,
+    pub payload: Vec<u8>,
+    /// Ports that the probe is sent to first.
+    pub ports: Vec<RangeInclusive<u16>>,
+    /// How long to wait for the whole response.
+    pub wait: Duration,
+    pub matches: Vec<Match>,
+}
+
+impl ServiceProbe {
+    pub fn targets(&self, port: u16) -> bool {
+        self.ports.iter().any(|r| r.contains(&port))
+    }
+}
+
+#[derive(Debug)]
+pub struct Match {
+    pub service: String,
+    /// Whether it only tells the service, so that probes for it go on.
+    pub soft: bool,
+    pattern: Regex,
+    /// Fields of the service by their letter (e.g. `p` for the product),
+    /// where `$1` to `$9` stand for groups of the pattern.
+    fields: Vec<(char, String)>,
+}
+
+impl Match {
+    /// Describes the service that sent `response`, if it matches.
+    pub fn apply(&self, response: &[u8]) -> Option<Service> {
+        let captures = self.pattern.captures(response)?;
+        let mut service = Service {
+            name: self.service.clone(),
+            ..Default::default()
+        };
+
+        for (key, template) in &self.fields {
+            let value = Some(substitute(template, &captures)).filter(|v| !v.is_empty());
+            match key {
+                'p' => service.product = value,
+                'v' => service.version = value,
+                'i' => service.info = value,
+                'h' => service.hostname = value,
+                'o' => service.os = value,
+                'd' => service.device = value,
+                _ => (),
+            }
+        }
+
+        Some(service)
+    }
+}
+
+/// Replaces `$1` to `$9` in `template` with the groups they stand for,
+/// leaving out unprintable bytes.
+fn substitute(template: &str, captures: &Captures) -> String {
+    let mut value = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().and_then(|n| n.to_digit(10))) {
+            ('$', Some(group)) => {
+                chars.next();
+                if let Some(group) = captures.get(group as usize) {
+                    value.extend(
+                        group
+                            .as_bytes()
+                            .iter()
+                            .filter(|b| b.is_ascii_graphic() || **b == b' ')
+                            .map(|&b| b as char),
+                    );
+                }
+            }
+            _ => value.push(c),
+        }
+    }
+
+    value.trim().to_string()
+}
+
+/// Reads the probes in `raw`, or reports the first line that isn't valid.
+pub fn parse(raw: &str) -> Result<Vec<ServiceProbe>, String> {
+    let mut probes: Vec<ServiceProbe> = vec![];
+
+    for (n, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |why: &str| format!("line {}: {}", n + 1, why);
+        let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        if directive == "Probe" {
+            let (protocol, rest) = rest.split_once(' ').ok_or_else(|| invalid("no protocol"))?;
+            if protocol != "TCP" {
+                return Err(invalid("only TCP probes are supported"));
+            }
+            let (name, rest) = rest.split_once(' ').ok_or_else(|| invalid("no payload"))?;
+            let payload = rest
+                .strip_prefix('q')
+                .and_then(delimited)
+                .ok_or_else(|| invalid("payload isn't delimited"))?
+                .0;
+
+            probes.push(ServiceProbe {
+                name: name.to_string(),
+                payload: unescape(payload).ok_or_else(|| invalid("invalid escape"))?,
+                ports: vec![],
+                wait: DEFAULT_WAIT,
+                matches: vec![],
+            });
+            continue;
+        }
+
+        let probe = probes
+            .last_mut()
+            .ok_or_else(|| invalid("directive before any probe"))?;
+        match directive {
+            "ports" => {
+                probe.ports = rest
+                    .split(',')
+                    .map(|rp| match rp.split_once('-') {
+                        Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+                        None => rp.parse().ok().map(|p| p..=p),
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| invalid("invalid ports"))?;
+            }
+            "totalwaitms" => {
+                let ms = rest.parse().map_err(|_| invalid("invalid wait"))?;
+                probe.wait = Duration::from_millis(ms);
+            }
+            "match" | "softmatch" => {
+                probe
+                    .matches
+                    .push(parse_match(rest, directive == "softmatch").map_err(|e| invalid(&e))?);
+            }
+            // Anything else of nmap's format is left out.
+            _ => (),
+        }
+    }
+
+    Ok(probes)
+}
+
+fn parse_match(raw: &str, soft: bool) -> Result<Match, String> {
+    let (service, rest) = raw.split_once(' ').ok_or("no pattern")?;
+    let (pattern, rest) = rest
+        .strip_prefix('m')
+        .and_then(delimited)
+        .ok_or("pattern isn't delimited")?;
+
+    let flags = rest.split(' ').next().unwrap_or_default();
+    let pattern = RegexBuilder::new(pattern)
+        .unicode(false)
+        .dot_matches_new_line(flags.contains('s'))
+        .case_insensitive(flags.contains('i'))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut fields = vec![];
+    let mut rest = &rest[flags.len()..];
+    loop {
+        rest = rest.trim_start();
+        let Some(key) = rest.chars().next() else {
+            break;
+        };
+
+        // CPE names, as `cpe:/a:vendor:product/`, are left out.
+        let field = match rest.strip_prefix("cpe:") {
+            Some(cpe) => cpe,
+            None => &rest[1..],
+        };
+        let (value, after) = delimited(field).ok_or("field isn't delimited")?;
+        if !rest.starts_with("cpe:") {
+            fields.push((key, value.to_string()));
+        }
+
+        // Flags of the field (e.g. the `a` of CPE names) are skipped.
+        rest = after.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    }
+
+    Ok(Match {
+        service: service.to_string(),
+        soft,
+        pattern,
+        fields,
+    })
+}
+
+/// Splits `raw` after the text enclosed by its first character.
+fn delimited(raw: &str) -> Option<(&str, &str)> {
+    let delimiter = raw.chars().next()?;
+    let raw = &raw[delimiter.len_utf8()..];
+    let end = raw.find(delimiter)?;
+
+    Some((&raw[..end], &raw[end + delimiter.len_utf8()..]))
+}
+
+/// Decodes the C escapes of a payload (e.g. `\r\n` or `\x00`).
+fn unescape(raw: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        bytes.push(match chars.next()? {
+            'r' => b'\r',
+            'n' => b'\n',
+            't' => b'\t',
+            '0' => 0,
+            'x' => {
+                let hex = [chars.next()?, chars.next()?].iter().collect::<String>();
+                u8::from_str_radix(&hex, 16).ok()?
+            }
+            c => c as u8,
+        });
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_probes_and_matches() {
+        let probes = parse(
+            "# Comment\n\
+            Probe TCP GetRequest q|GET / HTTP/1.0\\r\\n\\r\\n|\n\
+            rarity 1\n\
+            ports 80,8000-8010\n\
+            totalwaitms 2000\n\
+            match http m|^HTTP/1\\.[01] .*Server: nginx/([\\d.]+)|s p/nginx/ v/$1/ cpe:/a:nginx:nginx:$1/\n\
+            softmatch http m|^http|i\n",
+        )
+        .unwrap();
+
+        let probe = &probes[0];
+        assert_eq!(probe.payload, b"GET / HTTP/1.0\r\n\r\n");
+        assert!(probe.targets(80) && probe.targets(8005) && !probe.targets(443));
+        assert_eq!(probe.wait, Duration::from_millis(2000));
+
+        let service = probe.matches[0]
+            .apply(b"HTTP/1.1 200 OK\r\nServer: nginx/1.25.3\r\n\r\n")
+            .unwrap();
+        assert_eq!(service.product.as_deref(), Some("nginx"));
+        assert_eq!(service.version.as_deref(), Some("1.25.3"));
+        assert!(probe.matches[1].soft && probe.matches[1].apply(b"HTTP/1.0").is_some());
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        assert_eq!(
+            parse("match ssh m|^SSH|").unwrap_err(),
+            "line 1: directive before any probe"
+        );
+        assert!(parse("Probe TCP NULL q||\nmatch ssh m|(|").is_err());
+    }
+}
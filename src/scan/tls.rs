@@ -0,0 +1,310 @@
+//! TLS inspection: open TCP ports are sent a handshake, which tells the
+//! protocol version and cipher that the server picks along with the
+//! certificate it presents. Certificates are never trusted or rejected,
+//! only described.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use once_cell::sync::Lazy;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, ProtocolVersion, SignatureScheme,
+};
+use serde::Serialize;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use super::{ser, HostResult, PortState, Protocol};
+
+// Time to wait for a connection and each step of the handshake, unless the
+// scan sets a timeout.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(3000);
+
+static CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| {
+    let provider = Arc::new(ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+
+    Arc::new(config)
+});
+
+/// TLS served on a port, as its handshake tells.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TlsInfo {
+    /// Negotiated protocol version (e.g. `TLSv1.3`).
+    pub version: String,
+    /// Negotiated cipher suite (e.g. `TLS13_AES_256_GCM_SHA384`).
+    pub cipher: String,
+    /// Certificate of the server, if it sent one that could be parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<Certificate>,
+}
+
+impl TlsInfo {
+    /// Protocol, cipher and the gist of the certificate on a single line,
+    /// e.g. `TLSv1.3 TLS13_AES_256_GCM_SHA384; localhost; expires
+    /// 2126-09-23T20:57:41Z; self-signed`.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} {}", self.version, self.cipher);
+        if let Some(cert) = &self.certificate {
+            if let Some(name) = &cert.common_name {
+                summary.push_str(&format!("; {}", name));
+            }
+            summary.push_str(&format!(
+                "; {} {}",
+                match cert.expired {
+                    true => "expired",
+                    false => "expires",
+                },
+                humantime::format_rfc3339_seconds(cert.not_after)
+            ));
+            if cert.self_signed {
+                summary.push_str("; self-signed");
+            }
+        }
+
+        summary
+    }
+}
+
+/// What the certificate of a server says about it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Certificate {
+    /// Distinguished name of the subject (e.g. `CN=localhost, O=pscan`).
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub common_name: Option<String>,
+    /// Subject alternative names, as `DNS:` names and `IP:` addresses.
+    pub alt_names: Vec<String>,
+    pub issuer: String,
+    #[serde(serialize_with = "ser::rfc3339")]
+    pub not_before: SystemTime,
+    #[serde(serialize_with = "ser::rfc3339")]
+    pub not_after: SystemTime,
+    /// Whether it expired by the time of the scan.
+    pub expired: bool,
+    /// Whether it was issued by its own subject.
+    pub self_signed: bool,
+}
+
+impl Certificate {
+    fn parse(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let time = |timestamp: i64| match u64::try_from(timestamp) {
+            Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+            Err(_) => UNIX_EPOCH - Duration::from_secs(timestamp.unsigned_abs()),
+        };
+
+        let alt_names = match cert.subject_alternative_name() {
+            Ok(Some(san)) => san
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(dns) => Some(format!("DNS:{}", dns)),
+                    GeneralName::IPAddress(raw) => {
+                        let ip = match raw.len() {
+                            4 => IpAddr::from(<[u8; 4]>::try_from(*raw).ok()?),
+                            _ => IpAddr::from(<[u8; 16]>::try_from(*raw).ok()?),
+                        };
+                        Some(format!("IP:{}", ip))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(String::from);
+        let not_after = time(cert.validity().not_after.timestamp());
+
+        Some(Self {
+            subject: cert.subject().to_string(),
+            common_name,
+            alt_names,
+            issuer: cert.issuer().to_string(),
+            not_before: time(cert.validity().not_before.timestamp()),
+            not_after,
+            expired: not_after < SystemTime::now(),
+            self_signed: cert.subject().as_raw() == cert.issuer().as_raw(),
+        })
+    }
+}
+
+/// Accepts every certificate, so that the ones that wouldn't be trusted
+/// are described too. Handshake signatures are still checked.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Inspects the TLS served on every open TCP port of `hosts`.
+pub fn inspect(hosts: &mut [HostResult], timeout: Option<Duration>) {
+    let timeout = timeout.unwrap_or(HANDSHAKE_TIMEOUT);
+
+    hosts
+        .iter_mut()
+        .flat_map(|hr| {
+            let ip = hr.ip;
+            hr.ports
+                .iter_mut()
+                .filter(|pr| pr.state == PortState::Open && pr.protocol == Protocol::Tcp)
+                .map(move |pr| (ip, pr))
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .for_each(|(ip, pr)| {
+            let addr = SocketAddr::new(ip, pr.port);
+            match handshake(&addr, timeout) {
+                Ok(tls) => pr.tls = Some(tls),
+                Err(e) => log::debug!("No TLS handshake with `{}`: {}", addr, e),
+            }
+        });
+}
+
+/// Connects to `addr` and goes through a TLS handshake with it.
+fn handshake(addr: &SocketAddr, timeout: Duration) -> io::Result<TlsInfo> {
+    let mut stream = TcpStream::connect_timeout(addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut conn = ClientConnection::new(CONFIG.clone(), ServerName::from(addr.ip()))
+        .map_err(io::Error::other)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)?;
+    }
+
+    let version = match conn.protocol_version() {
+        Some(ProtocolVersion::TLSv1_2) => String::from("TLSv1.2"),
+        Some(ProtocolVersion::TLSv1_3) => String::from("TLSv1.3"),
+        version => format!("{:?}", version),
+    };
+    let cipher = conn
+        .negotiated_cipher_suite()
+        .map(|suite| format!("{:?}", suite.suite()))
+        .unwrap_or_default();
+    let certificate = conn
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(|cert| Certificate::parse(cert));
+
+    Ok(TlsInfo {
+        version,
+        cipher,
+        certificate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use rustls::{pki_types::PrivateKeyDer, server::ServerConfig, ServerConnection, StreamOwned};
+
+    use super::*;
+
+    #[test]
+    fn describes_expired_self_signed_certificate() {
+        let cert = CertificateDer::from(&include_bytes!("tls/expired.crt.der")[..]);
+        let key = PrivateKeyDer::try_from(&include_bytes!("tls/expired.key.der")[..]).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut tls = StreamOwned::new(conn, stream);
+            while tls.conn.is_handshaking() {
+                tls.conn.complete_io(&mut tls.sock).unwrap();
+            }
+        });
+
+        let tls = handshake(&addr, Duration::from_secs(1)).unwrap();
+        assert_eq!(tls.version, "TLSv1.3");
+        assert!(tls.cipher.starts_with("TLS13_"));
+
+        let cert = tls.certificate.as_ref().unwrap();
+        assert_eq!(cert.subject, "CN=localhost, O=pscan");
+        assert_eq!(cert.alt_names, ["DNS:localhost", "IP:127.0.0.1"]);
+        assert!(cert.expired && cert.self_signed);
+        assert!(tls
+            .summary()
+            .ends_with("; localhost; expired 2021-01-01T00:00:00Z; self-signed"));
+    }
+
+    #[test]
+    fn plain_ports_fail_the_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            io::Write::write_all(&mut stream, b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+        });
+
+        assert!(handshake(&addr, Duration::from_secs(1)).is_err());
+    }
+}